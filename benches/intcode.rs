@@ -0,0 +1,58 @@
+// Times the day 9 BOOST program with and without the decode cache, and
+// compiled.
+//
+//     cargo bench --bench intcode
+
+use advent2019::intcode::compile::{Compiled, CompiledComputer};
+use advent2019::intcode::{read_program, Computer, State};
+
+use std::rc::Rc;
+
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 20;
+
+fn main() {
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/day09.txt");
+    let program = read_program(path).expect("failed to read program");
+
+    let uncached = time(&program, false);
+    let cached = time(&program, true);
+    let compiled = time_compiled(&program);
+    println!("day09 part 2, {} runs", RUNS);
+    println!("  without decode cache: {:?} per run", uncached);
+    println!("  with decode cache:    {:?} per run", cached);
+    println!("  compiled:             {:?} per run", compiled);
+    println!(
+        "  speedup: {:.2}x cached, {:.2}x compiled",
+        uncached.as_secs_f64() / cached.as_secs_f64(),
+        uncached.as_secs_f64() / compiled.as_secs_f64()
+    );
+}
+
+fn time(program: &[i64], cache: bool) -> Duration {
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let mut cpu = Computer::new(program);
+        cpu.set_decode_cache(cache);
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        cpu.input(2).unwrap();
+        assert!(matches!(cpu.execute(), Ok(State::Output(_))));
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+    }
+    start.elapsed() / RUNS
+}
+
+// Compiles once, outside the timed loop.
+fn time_compiled(program: &[i64]) -> Duration {
+    let compiled = Rc::new(Compiled::new(program));
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let mut cpu = CompiledComputer::new(compiled.clone());
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        cpu.input(2).unwrap();
+        assert!(matches!(cpu.execute(), Ok(State::Output(_))));
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+    }
+    start.elapsed() / RUNS
+}
@@ -0,0 +1,178 @@
+use advent2019::intcode;
+use advent2019::intcode::trace::LineTracer;
+use advent2019::intcode::State;
+
+use std::env;
+use std::fs;
+use std::io::BufWriter;
+use std::process;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        usage();
+    }
+
+    match args[0].as_str() {
+        "disasm" => println!("{}", intcode::disasm::listing(&load_program(&args[1]))),
+        "dot" if args.len() >= 3 => {
+            let dot = intcode::cfg::Cfg::new(&load_program(&args[1])).dot();
+            fs::write(&args[2], dot).unwrap_or_else(|e| fail(&args[2], e));
+        }
+        "decompile" => print!("{}", intcode::decompile::decompile(&load_program(&args[1]))),
+        "debug" => {
+            let program = load_program(&args[1]);
+            intcode::debugger::debug_console_program(&program).unwrap_or_else(|e| fail("debug", e));
+        }
+        "run" => {
+            let script = script(&args[2..]);
+            intcode::execute_scripted_program(&load_program(&args[1]), &script, true)
+                .unwrap_or_else(|e| fail("program failed", e));
+        }
+        "ascii" => {
+            let program = load_program(&args[1]);
+            intcode::ascii::execute_ascii_console_program(&program)
+                .unwrap_or_else(|e| fail("program failed", e));
+        }
+        "trace" if args.len() >= 3 => trace(&load_program(&args[1]), &args[2], &args[3..]),
+        "profile" => profile(&load_program(&args[1]), &args[2..]),
+        "smc" => self_modifying(&load_program(&args[1]), &args[2..]),
+        "cover" => coverage(&load_program(&args[1]), None, &args[2..]),
+        "lcov" if args.len() >= 3 => coverage(&load_program(&args[1]), Some(&args[2]), &args[3..]),
+        "asm" => {
+            let source =
+                fs::read_to_string(&args[1]).unwrap_or_else(|e| fail("failed to read source", e));
+            let program = intcode::asm::assemble(&source).unwrap_or_else(|e| fail(&args[1], e));
+            let cells: Vec<_> = program.iter().map(|x| x.to_string()).collect();
+            println!("{}", cells.join(","));
+        }
+        _ => usage(),
+    };
+}
+
+// Runs program on the given inputs, printing its outputs and writing a line
+// per executed instruction to trace_path.
+fn trace(program: &[i64], trace_path: &str, inputs: &[String]) {
+    let file = fs::File::create(trace_path).unwrap_or_else(|e| fail(trace_path, e));
+    let mut cpu = intcode::Computer::new(program);
+    cpu.set_tracer(Box::new(LineTracer::new(BufWriter::new(file))));
+    run(&mut cpu, inputs);
+
+    if let Some(mut tracer) = cpu.take_tracer() {
+        tracer.finish().unwrap_or_else(|e| fail(trace_path, e));
+    }
+}
+
+// Runs program on the given inputs, printing its outputs followed by a
+// hot-spot report.
+fn profile(program: &[i64], inputs: &[String]) {
+    let mut cpu = intcode::Computer::new(program);
+    cpu.enable_profiling();
+    run(&mut cpu, inputs);
+
+    let profile = cpu.take_profile().unwrap();
+    print!("\n{}", profile.report(&cpu.ram, 20));
+}
+
+// Runs program on the given inputs, printing its outputs followed by every
+// write it made into code.
+fn self_modifying(program: &[i64], inputs: &[String]) {
+    let mut cpu = intcode::Computer::new(program);
+    cpu.enable_code_watch();
+    run(&mut cpu, inputs);
+
+    println!();
+    let writes = cpu.take_code_writes().unwrap();
+    if writes.is_empty() {
+        println!("no writes into code");
+    }
+    for write in writes {
+        println!("{}", write);
+    }
+}
+
+// Runs program on the given inputs, printing its outputs followed by an
+// annotated listing, or writing an LCOV tracefile to lcov_path. The
+// tracefile refers to the listing, which is saved next to it.
+fn coverage(program: &[i64], lcov_path: Option<&str>, inputs: &[String]) {
+    let mut cpu = intcode::Computer::new(program);
+    cpu.enable_coverage();
+    run(&mut cpu, inputs);
+
+    let coverage = cpu.take_coverage().unwrap();
+    match lcov_path {
+        None => print!("\n{}", coverage.listing(program)),
+        Some(path) => {
+            let listing_path = format!("{}.asm", path);
+            fs::write(&listing_path, intcode::disasm::listing(program))
+                .unwrap_or_else(|e| fail(&listing_path, e));
+            fs::write(path, coverage.lcov(program, &listing_path))
+                .unwrap_or_else(|e| fail(path, e));
+        }
+    }
+}
+
+// Runs cpu until it halts or needs more input than given, printing outputs.
+fn run(cpu: &mut intcode::Computer, inputs: &[String]) {
+    let mut inputs = inputs
+        .iter()
+        .map(|x| x.parse::<i64>().unwrap_or_else(|e| fail(x, e)));
+
+    loop {
+        match cpu.execute().unwrap_or_else(|e| fail("program failed", e)) {
+            State::InputRequested => match inputs.next() {
+                Some(x) => cpu.input(x).unwrap(),
+                None => {
+                    eprintln!("ran out of input");
+                    break;
+                }
+            },
+            State::Output(x) => println!("{}", x),
+            State::Halted => break,
+        }
+    }
+}
+
+// Collects inputs from the command line. An argument of the form @file is
+// replaced by the inputs in that file.
+fn script(args: &[String]) -> Vec<i64> {
+    let mut inputs = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('@') {
+            Some(path) => inputs.extend(
+                intcode::read_script(path).unwrap_or_else(|e| fail("failed to read script", e)),
+            ),
+            None => inputs.push(arg.parse().unwrap_or_else(|e| fail(arg, e))),
+        }
+    }
+    inputs
+}
+
+fn load_program(path: &str) -> Vec<i64> {
+    intcode::read_program(path).unwrap_or_else(|e| fail("failed to read program", e))
+}
+
+fn fail<E: std::fmt::Display>(context: &str, e: E) -> ! {
+    eprintln!("{}: {}", context, e);
+    process::exit(1);
+}
+
+fn usage() -> ! {
+    eprintln!("usage: intcode <command> <file> [args...]");
+    eprintln!();
+    eprintln!("commands:");
+    eprintln!("    disasm    print a disassembly listing of a program");
+    eprintln!("    dot       write the control-flow graph: dot <program> <dot file>");
+    eprintln!("    decompile print C-like pseudocode for a program");
+    eprintln!("    debug     run a program in the interactive debugger");
+    eprintln!("    run       run a program on scripted input, then interactively:");
+    eprintln!("              run <program> [input... | @script file...]");
+    eprintln!("    ascii     run a program that reads and writes text");
+    eprintln!("    trace     run a program: trace <program> <trace file> [input...]");
+    eprintln!("    profile   run a program and report hot spots: profile <program> [input...]");
+    eprintln!("    smc       run a program and report writes into code: smc <program> [input...]");
+    eprintln!("    cover     run a program and annotate its listing: cover <program> [input...]");
+    eprintln!("    lcov      run a program: lcov <program> <lcov file> [input...]");
+    eprintln!("    asm       assemble a source file and print the program");
+    process::exit(2);
+}
@@ -1,4 +1,6 @@
 use crate::intcode;
+use crate::intcode::symbolic::{self, Expr, Symbolic};
+use crate::intcode::State;
 
 pub fn main() {
     let program = intcode::read_program("data/day02.txt").expect("failed to read data");
@@ -7,14 +9,23 @@ pub fn main() {
 }
 
 fn part1(program: &[i64]) -> i64 {
-    execute_with_params(&program, 12, 2)
+    execute_with_params(program, 12, 2)
 }
 
 fn part2(input: &[i64]) -> Option<i64> {
-    for a in 0..input.len() as i64 {
-        for b in 0..input.len() as i64 {
-            let ret = execute_with_params(&input, a, b);
-            if ret == 19690720 {
+    // ram[0] is usually linear in the noun and verb, so solve for them
+    // directly and only try every pair when that doesn't work.
+    let limit = input.len() as i64;
+    let bounds = [("noun", 0..limit), ("verb", 0..limit)];
+    if let Some(expr) = output_expr(input) {
+        if let Some(values) = symbolic::solve(&expr, 19690720, &bounds) {
+            return Some(values[0] * 100 + values[1]);
+        }
+    }
+
+    for a in 0..limit {
+        for b in 0..limit {
+            if execute_with_params(input, a, b) == 19690720 {
                 return Some(a * 100 + b);
             }
         }
@@ -23,10 +34,52 @@ fn part2(input: &[i64]) -> Option<i64> {
     None
 }
 
+// Runs the program with the noun and verb left as symbols and returns what
+// ends up in ram[0].
+fn output_expr(input: &[i64]) -> Option<Expr> {
+    let mut cpu = Symbolic::new(input);
+    if !cpu.set_symbol(1, "noun") || !cpu.set_symbol(2, "verb") {
+        return None;
+    }
+    match cpu.execute() {
+        Ok(State::Halted) => Some(cpu.ram.get(0)),
+        _ => None,
+    }
+}
+
 fn execute_with_params(input: &[i64], a: i64, b: i64) -> i64 {
     let mut cpu = intcode::Computer::new(input);
-    cpu.ram[1] = a;
-    cpu.ram[2] = b;
-    cpu.execute();
+    let set = cpu.ram.set(1, a) && cpu.ram.set(2, b);
+    assert!(set, "no room for the noun and verb");
+    cpu.execute().expect("program failed");
     cpu.ram[0]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parts() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/day02.txt");
+        let program = intcode::read_program(path).unwrap();
+        assert_eq!(part1(&program), 3101844);
+        assert_eq!(
+            output_expr(&program).unwrap().to_string(),
+            "230400*noun + verb + 337042"
+        );
+        assert_eq!(part2(&program), Some(8478));
+    }
+
+    #[test]
+    fn test_part2_fallback() {
+        // mul [noun], [verb], [0]; the noun and verb are used as addresses,
+        // so ram[0] isn't linear in them.
+        let program = [2, 0, 0, 0, 99, 19690720, 1];
+        assert_eq!(
+            output_expr(&program).unwrap().to_string(),
+            "mem[noun]*mem[verb]"
+        );
+        assert_eq!(part2(&program), Some(105));
+    }
+}
@@ -28,17 +28,20 @@ fn part2(program: &[i64]) -> i64 {
     ret
 }
 
+// Runs each amp on its own thread, with the last one feeding the first.
 fn feedbad_signal(program: &[i64], phases: &[i64]) -> i64 {
-    let mut output = 0;
-    let mut amps = AmpSeries::new(program, phases);
-    loop {
-        output = match amps.run(output) {
-            Some(x) => x,
-            None => break,
-        }
+    let cpus = phases
+        .iter()
+        .map(|_| intcode::Computer::new(program))
+        .collect();
+    let mut amps = intcode::Cluster::ring(cpus);
+    for (i, &phase) in phases.iter().enumerate() {
+        amps.push_input(i, phase);
     }
+    amps.push_input(0, 0);
 
-    output
+    let last = amps.run().pop().unwrap().expect("program failed");
+    *last.outputs.last().unwrap()
 }
 
 fn max_5amp_signal(program: &[i64]) -> i64 {
@@ -87,33 +90,21 @@ impl AmpSeries {
 }
 
 struct Amp {
-    cpu: intcode::Computer,
+    machine: intcode::Machine,
 }
 
 impl Amp {
     fn new(program: &[i64], phase: i64) -> Amp {
-        let mut amp = Amp {
-            cpu: intcode::Computer::new(program),
-        };
-        amp.cpu.execute();
-        amp.cpu.input(phase);
-
-        amp
+        let mut machine = intcode::Machine::new(program);
+        machine.push_input(phase);
+        Amp { machine }
     }
 
+    // Returns the amp's next output, or None once it has halted.
     fn run(&mut self, input: i64) -> Option<i64> {
-        if self.cpu.execute() == intcode::State::Halted {
-            return None;
-        }
-        self.cpu.input(input);
-
-        let ret = match self.cpu.execute() {
-            intcode::State::Output(x) => x,
-            intcode::State::Halted => return None,
-            _ => panic!("expected output or halt"),
-        };
-
-        Some(ret)
+        self.machine.push_input(input);
+        self.machine.run().expect("program failed");
+        self.machine.pop_output()
     }
 }
 
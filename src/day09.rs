@@ -2,5 +2,26 @@ use crate::intcode;
 
 pub fn main() {
     let program = intcode::read_program("data/day09.txt").expect("failed to read program");
-    intcode::execute_console_program(&program);
+    println!("Part 1: {}", run(&program, 1));
+    println!("Part 2: {}", run(&program, 2));
+}
+
+// Runs the program unattended on a single input and returns its last output.
+fn run(program: &[i64], input: i64) -> i64 {
+    let outputs =
+        intcode::execute_scripted_program(program, &[input], false).expect("program failed");
+    *outputs.last().expect("no output")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/src/data/day09.txt");
+        let program = intcode::read_program(path).unwrap();
+        assert_eq!(run(&program, 1), 3989758265);
+        assert_eq!(run(&program, 2), 76791);
+    }
 }
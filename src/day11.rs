@@ -1,5 +1,4 @@
 use crate::intcode;
-use crate::intcode::State;
 
 use std::collections::HashMap;
 use std::convert::{TryFrom, TryInto};
@@ -84,14 +83,14 @@ struct Point {
 }
 
 struct Robot {
-    cpu: intcode::Computer,
+    machine: intcode::Machine,
     direction: Direction,
 }
 
 impl Robot {
     fn new(program: &[i64]) -> Robot {
         Robot {
-            cpu: intcode::Computer::new(program),
+            machine: intcode::Machine::new(program),
             direction: Direction::North,
         }
     }
@@ -99,20 +98,11 @@ impl Robot {
     // Takes current tile and color of that tile. Returns the new color of the
     // tile and the next tile to go to. It will return None when it halts.
     fn step(&mut self, loc: &Point, color: Color) -> Option<(Color, Point)> {
-        if self.cpu.execute() == State::Halted {
-            return None;
-        }
-        self.cpu.input(color as i64);
-
-        let new_color = match self.cpu.execute() {
-            State::Output(x) => x.try_into().unwrap(),
-            _ => panic!("expected output"),
-        };
+        self.machine.push_input(color as i64);
+        self.machine.run().expect("program failed");
 
-        let turn = match self.cpu.execute() {
-            State::Output(x) => x,
-            _ => panic!("expected output"),
-        };
+        let new_color = self.machine.pop_output()?.try_into().unwrap();
+        let turn = self.machine.pop_output().expect("expected turn");
 
         self.direction = match turn {
             0 => self.direction.left(),
@@ -1,4 +1,4 @@
-use crate::intcode::{self, State};
+use crate::intcode;
 
 use std::cmp::max;
 use std::collections::{HashMap, HashSet, VecDeque};
@@ -140,25 +140,22 @@ fn explore(droid: &mut Droid) -> HashMap<Point, Status> {
 }
 
 struct Droid {
-    cpu: intcode::Computer,
+    machine: intcode::Machine,
     location: Point,
 }
 
 impl Droid {
     fn new(program: &[i64]) -> Droid {
         Droid {
-            cpu: intcode::Computer::new(program),
+            machine: intcode::Machine::new(program),
             location: Point { x: 0, y: 0 },
         }
     }
 
     fn step(&mut self, dir: Direction) -> (Status, Point) {
-        self.cpu.execute();
-        self.cpu.input(dir as i64);
-        let output = match self.cpu.execute() {
-            State::Output(x) => x,
-            _ => panic!("unexpected intcode state"),
-        };
+        self.machine.push_input(dir as i64);
+        self.machine.run().expect("program failed");
+        let output = self.machine.pop_output().expect("expected status");
 
         let status: Status = output.try_into().unwrap();
         let point = dir.step(&self.location);
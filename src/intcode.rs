@@ -1,10 +1,47 @@
-use std::error::Error;
+use std::error;
+use std::fmt;
 use std::fs;
 use std::io;
 use std::io::Write;
 use std::path::Path;
+use std::time::Instant;
 
-pub fn read_program<P: AsRef<Path>>(path: P) -> Result<Vec<i64>, Box<dyn Error>> {
+pub mod ascii;
+pub mod asm;
+pub mod cfg;
+pub mod cluster;
+pub mod compile;
+mod coverage;
+mod custom;
+pub mod debugger;
+pub mod decompile;
+pub mod disasm;
+pub mod fuzz;
+mod history;
+mod memory;
+mod network;
+pub mod port;
+mod profile;
+mod selfmod;
+mod snapshot;
+pub mod symbolic;
+pub mod trace;
+mod word;
+
+pub use cluster::{Cluster, Finished};
+pub use coverage::{Branch, Coverage};
+pub use custom::{CustomOpcode, RegisterError};
+use history::History;
+pub use memory::{Memory, DEFAULT_LIMIT};
+pub use network::{NatEvent, Network, NAT_ADDRESS};
+pub use port::{IterSource, Machine, Sink, Source};
+pub use profile::Profile;
+use selfmod::CodeWatch;
+pub use selfmod::CodeWrite;
+pub use trace::Tracer;
+pub use word::{widen, Word};
+
+pub fn read_program<P: AsRef<Path>>(path: P) -> Result<Vec<i64>, Box<dyn error::Error>> {
     let raw = fs::read_to_string(path)?;
     raw.trim()
         .split_terminator(',')
@@ -14,172 +51,523 @@ pub fn read_program<P: AsRef<Path>>(path: P) -> Result<Vec<i64>, Box<dyn Error>>
         })
 }
 
-pub fn execute_console_program(program: &[i64]) {
-    fn get_input() -> i64 {
-        let mut buf = String::new();
-        loop {
-            buf.clear();
-            print!("> ");
-            let _ = io::stdout().lock().flush();
-            let result = io::stdin().read_line(&mut buf);
-            if result.is_err() {
-                println!();
-                continue;
-            }
+// Reads a script of inputs for execute_scripted_program. Values may be
+// separated by commas or whitespace.
+pub fn read_script<P: AsRef<Path>>(path: P) -> Result<Vec<i64>, Box<dyn error::Error>> {
+    let raw = fs::read_to_string(path)?;
+    raw.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|x| !x.is_empty())
+        .try_fold(Vec::new(), |mut acc, x| {
+            acc.push(x.parse()?);
+            Ok(acc)
+        })
+}
 
-            let trimmed = buf.trim();
-            match trimmed.parse() {
-                Ok(x) => return x,
-                Err(_) => println!("bad input"),
-            };
-        }
-    }
+pub fn execute_console_program(program: &[i64]) -> Result<(), Error> {
+    execute_scripted_program(program, &[], true).map(|_| ())
+}
 
+// Runs program, taking input from script first. Once the script runs out,
+// input is read from stdin if interactive is set, and otherwise the program
+// is stopped. Outputs are printed as they are produced and returned.
+pub fn execute_scripted_program(
+    program: &[i64],
+    script: &[i64],
+    interactive: bool,
+) -> Result<Vec<i64>, Error> {
+    let mut script = script.iter().cloned();
+    let mut outputs = Vec::new();
     let mut cpu = Computer::new(program);
     loop {
-        match cpu.execute() {
-            State::InputRequested => cpu.input(get_input()),
-            State::Output(x) => println!("{}", x),
-            State::Halted => break,
+        match cpu.execute()? {
+            State::InputRequested => match script.next() {
+                Some(x) => cpu.input(x)?,
+                None if interactive => cpu.input(read_console_input())?,
+                None => return Ok(outputs),
+            },
+            State::Output(x) => {
+                println!("{}", x);
+                outputs.push(x);
+            }
+            State::Halted => return Ok(outputs),
         };
     }
 }
 
-pub struct Computer {
-    pub ram: [i64; 32768],
+fn read_console_input() -> i64 {
+    let mut buf = String::new();
+    loop {
+        buf.clear();
+        print!("> ");
+        let _ = io::stdout().lock().flush();
+        let result = io::stdin().read_line(&mut buf);
+        if result.is_err() {
+            println!();
+            continue;
+        }
+
+        let trimmed = buf.trim();
+        match trimmed.parse() {
+            Ok(x) => return x,
+            Err(_) => println!("bad input"),
+        };
+    }
+}
+
+// An intcode machine whose memory cells hold values of type W.
+pub struct Computer<W = i64> {
+    pub ram: Memory<W>,
     pc: usize,
     relative_base: i64,
+    // Number of instructions executed so far.
+    instructions: u64,
+    // Address and value written by the last executed instruction, if any.
+    last_write: Option<(usize, W)>,
+    tracer: Option<Box<dyn Tracer<W> + Send>>,
+    history: Option<History<W>>,
+    // Address written by the last instruction and its previous value, kept
+    // only while history is enabled or code is watched.
+    overwritten: Option<(usize, W)>,
+    profile: Option<Profile>,
+    code_watch: Option<CodeWatch<W>>,
+    coverage: Option<Coverage>,
+    // Decoded opcode at each address along with the raw cell it was decoded
+    // from. An entry is stale once the cell no longer matches, which happens
+    // when code is overwritten. None when the cache is disabled.
+    decode_cache: Option<Vec<Option<(W, Opcode)>>>,
+    // Opcodes added with register_opcode.
+    custom: Vec<(CustomOpcode, custom::Handler<W>)>,
+}
+
+// Clones the machine state. The clone has no tracer, history, profile, code
+// watch or coverage, and shares custom opcode handlers.
+impl<W: Word> Clone for Computer<W> {
+    fn clone(&self) -> Computer<W> {
+        Computer {
+            ram: self.ram.clone(),
+            pc: self.pc,
+            relative_base: self.relative_base,
+            instructions: self.instructions,
+            last_write: None,
+            tracer: None,
+            history: None,
+            overwritten: None,
+            profile: None,
+            code_watch: None,
+            coverage: None,
+            decode_cache: self.decode_cache.clone(),
+            custom: self.custom.clone(),
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub enum State {
+pub enum State<W = i64> {
     InputRequested,
-    Output(i64),
+    Output(W),
     Halted,
 }
 
+// What execute_with_budget stopped for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Budgeted<W = i64> {
+    // A state execute would have returned.
+    Stopped(State<W>),
+    // The budget ran out. Calling execute_with_budget again resumes
+    // execution.
+    Exhausted,
+}
+
+// Limits how long execute_with_budget may run. A deadline is only checked
+// every DEADLINE_CHECK_INTERVAL instructions.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Budget {
+    pub instructions: Option<u64>,
+    pub deadline: Option<Instant>,
+}
+
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+// Only instructions below this address are kept in the decode cache.
+const DECODE_CACHE_LIMIT: usize = 1 << 16;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    BadOpcode { pc: usize, opcode: i64 },
+    BadParameterMode { pc: usize, mode: i64 },
+    ImmediateWrite { pc: usize },
+    NegativeAddress { pc: usize, address: i64 },
+    AddressOutOfRange { pc: usize, address: usize },
+    InputNotRequested { pc: usize },
+    // An add or mul result that doesn't fit in the word type.
+    Overflow { pc: usize },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BadOpcode { pc, opcode } => write!(f, "bad opcode {} at pc {}", opcode, pc),
+            Error::BadParameterMode { pc, mode } => {
+                write!(f, "bad parameter mode {} at pc {}", mode, pc)
+            }
+            Error::ImmediateWrite { pc } => {
+                write!(f, "opcode output cannot be immediate value at pc {}", pc)
+            }
+            Error::NegativeAddress { pc, address } => {
+                write!(f, "negative address {} at pc {}", address, pc)
+            }
+            Error::AddressOutOfRange { pc, address } => {
+                write!(f, "address {} beyond memory limit at pc {}", address, pc)
+            }
+            Error::InputNotRequested { pc } => write!(f, "input not requested at pc {}", pc),
+            Error::Overflow { pc } => write!(f, "arithmetic overflow at pc {}", pc),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
 impl Computer {
     pub fn new(program: &[i64]) -> Computer {
-        let mut cpu = Computer {
-            ram: [0; 32768],
+        Computer::with_memory_limit(program, DEFAULT_LIMIT)
+    }
+
+    // Creates a computer whose memory may hold at most limit cells, or the
+    // whole program if it is longer. Writes past the limit fail with
+    // Error::AddressOutOfRange.
+    pub fn with_memory_limit(program: &[i64], limit: usize) -> Computer {
+        Computer::from_words(program, limit)
+    }
+}
+
+impl<W: Word> Computer<W> {
+    // Creates a computer with a wider word type for a program as returned by
+    // read_program, for example Computer::<BigInt>::widened(&program).
+    pub fn widened(program: &[i64]) -> Computer<W> {
+        Computer::from_words(&widen(program), DEFAULT_LIMIT)
+    }
+
+    // Like with_memory_limit, for a program of any word type.
+    pub fn from_words(program: &[W], limit: usize) -> Computer<W> {
+        Computer {
+            ram: Memory::new(program, limit),
             pc: 0,
             relative_base: 0,
-        };
+            instructions: 0,
+            last_write: None,
+            tracer: None,
+            history: None,
+            overwritten: None,
+            profile: None,
+            code_watch: None,
+            coverage: None,
+            decode_cache: Some(Vec::new()),
+            custom: Vec::new(),
+        }
+    }
 
-        cpu.ram[..program.len()].copy_from_slice(program);
+    // Installs a tracer that is called after every executed instruction. The
+    // tracer must be Send so the Computer can be moved to another thread.
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer<W> + Send>) {
+        self.tracer = Some(tracer);
+    }
 
-        cpu
+    // Removes and returns the installed tracer.
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer<W> + Send>> {
+        self.tracer.take()
     }
 
-    // Provides input. If state is not State::InputRequested, this function
-    // panics.
-    pub fn input(&mut self, i: i64) {
-        if let Opcode::Input(m1) = parse_opcode(self.ram[self.pc]) {
-            self.instruction_output(m1, 1, i);
+    // Provides input. Returns an error if the current instruction is not an
+    // input instruction.
+    pub fn input(&mut self, i: W) -> Result<(), Error> {
+        self.last_write = None;
+        let opcode = self.decode()?;
+        if let Opcode::Input(m1) = opcode {
+            let (pc, relative_base) = (self.pc, self.relative_base);
+            self.instruction_output(m1, 1, i)?;
             self.pc += 2;
+            self.instructions += 1;
+            self.count(pc, opcode);
+            self.cover(pc, opcode);
+            self.trace(pc, relative_base, opcode, Vec::new());
+            self.watch_code(pc, opcode);
+            self.record(pc, relative_base, true);
+            Ok(())
         } else {
-            panic!("input not requested");
+            Err(Error::InputNotRequested { pc: self.pc })
         }
     }
 
-    pub fn execute(&mut self) -> State {
+    pub fn execute(&mut self) -> Result<State<W>, Error> {
         loop {
-            let opcode = parse_opcode(self.ram[self.pc]);
-            match opcode {
-                Opcode::Add(m1, m2, o) => self.binary_op(|a, b| a + b, m1, m2, o),
-                Opcode::Mul(m1, m2, o) => self.binary_op(|a, b| a * b, m1, m2, o),
-                Opcode::Input(_) => return State::InputRequested,
-                Opcode::Output(m1) => {
-                    let out = self.lookup_param(m1, 1);
-                    self.pc += 2;
-                    return State::Output(out);
-                }
-                Opcode::JumpIfTrue(m1, m2) => {
-                    let cond = self.lookup_param(m1, 1) != 0;
-                    let target = self.lookup_param(m2, 2);
-                    if cond {
-                        self.pc = target as usize;
-                    } else {
-                        self.pc += 3;
-                    }
-                }
-                Opcode::JumpIfFalse(m1, m2) => {
-                    let cond = self.lookup_param(m1, 1) != 0;
-                    let target = self.lookup_param(m2, 2);
-                    if !cond {
-                        self.pc = target as usize;
-                    } else {
-                        self.pc += 3;
-                    }
-                }
-                Opcode::LessThan(m1, m2, o) => {
-                    self.binary_op(|a, b| if a < b { 1 } else { 0 }, m1, m2, o)
+            if let Some(state) = self.step()? {
+                return Ok(state);
+            }
+        }
+    }
+
+    // Like execute, but returns Budgeted::Exhausted once budget runs out.
+    pub fn execute_with_budget(&mut self, budget: Budget) -> Result<Budgeted<W>, Error> {
+        let start = self.instructions;
+        loop {
+            let used = self.instructions - start;
+            if budget.instructions.is_some_and(|n| used >= n) {
+                return Ok(Budgeted::Exhausted);
+            }
+            if let Some(deadline) = budget.deadline {
+                if used.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                    return Ok(Budgeted::Exhausted);
                 }
-                Opcode::Equals(m1, m2, o) => {
-                    self.binary_op(|a, b| if a == b { 1 } else { 0 }, m1, m2, o)
+            }
+            if let Some(state) = self.step()? {
+                return Ok(Budgeted::Stopped(state));
+            }
+        }
+    }
+
+    // Turns the decode cache on or off. It is on by default; turning it off
+    // decodes every instruction from scratch.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    // Number of instructions executed so far.
+    pub fn instruction_count(&self) -> u64 {
+        self.instructions
+    }
+
+    // Executes a single instruction. Returns the state if the instruction
+    // requested input (in which case it is not executed), produced output or
+    // halted, and None otherwise.
+    pub fn step(&mut self) -> Result<Option<State<W>>, Error> {
+        self.last_write = None;
+        let opcode = self.decode()?;
+        if let Opcode::Input(_) = opcode {
+            return Ok(Some(State::InputRequested));
+        }
+
+        let (pc, relative_base) = (self.pc, self.relative_base);
+        let operands = match self.tracer {
+            Some(_) => self.read_operands(opcode)?,
+            None => Vec::new(),
+        };
+
+        let state = match opcode {
+            Opcode::Add(m1, m2, o) => self.binary_op(W::checked_add, m1, m2, o)?,
+            Opcode::Mul(m1, m2, o) => self.binary_op(W::checked_mul, m1, m2, o)?,
+            Opcode::Input(_) => unreachable!(),
+            Opcode::Output(m1) => {
+                let out = self.lookup_param(m1, 1)?;
+                self.pc += 2;
+                Some(State::Output(out))
+            }
+            Opcode::JumpIfTrue(m1, m2) => {
+                let cond = !self.lookup_param(m1, 1)?.is_zero();
+                let target = self.lookup_param(m2, 2)?;
+                if cond {
+                    self.pc = self.address(&target)?;
+                } else {
+                    self.pc += 3;
                 }
-                Opcode::AdjustRelativeBase(m1) => {
-                    self.relative_base += self.lookup_param(m1, 1);
-                    self.pc += 2;
+                None
+            }
+            Opcode::JumpIfFalse(m1, m2) => {
+                let cond = !self.lookup_param(m1, 1)?.is_zero();
+                let target = self.lookup_param(m2, 2)?;
+                if !cond {
+                    self.pc = self.address(&target)?;
+                } else {
+                    self.pc += 3;
                 }
-                Opcode::Halt => return State::Halted,
+                None
             }
-        }
+            Opcode::LessThan(m1, m2, o) => {
+                self.binary_op(|a, b| Some(W::from_i64((a < b) as i64)), m1, m2, o)?
+            }
+            Opcode::Equals(m1, m2, o) => {
+                self.binary_op(|a, b| Some(W::from_i64((a == b) as i64)), m1, m2, o)?
+            }
+            Opcode::AdjustRelativeBase(m1) => {
+                let offset = self.lookup_param(m1, 1)?;
+                self.relative_base = offset
+                    .to_i64()
+                    .and_then(|x| self.relative_base.checked_add(x))
+                    .ok_or(Error::Overflow { pc: self.pc })?;
+                self.pc += 2;
+                None
+            }
+            Opcode::Halt => Some(State::Halted),
+            Opcode::Custom(..) => {
+                self.execute_custom(opcode)?;
+                None
+            }
+        };
+
+        self.instructions += 1;
+        self.count(pc, opcode);
+        self.cover(pc, opcode);
+        self.trace(pc, relative_base, opcode, operands);
+        self.watch_code(pc, opcode);
+        self.record(pc, relative_base, matches!(state, Some(State::Output(_))));
+        Ok(state)
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
     }
 
-    fn binary_op<F>(&mut self, f: F, m1: ParameterMode, m2: ParameterMode, o: ParameterMode)
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    fn binary_op<F>(
+        &mut self,
+        f: F,
+        m1: ParameterMode,
+        m2: ParameterMode,
+        o: ParameterMode,
+    ) -> Result<Option<State<W>>, Error>
     where
-        F: Fn(i64, i64) -> i64,
+        F: Fn(&W, &W) -> Option<W>,
     {
-        let a = self.lookup_param(m1, 1);
-        let b = self.lookup_param(m2, 2);
-        self.instruction_output(o, 3, f(a, b));
+        let a = self.lookup_param(m1, 1)?;
+        let b = self.lookup_param(m2, 2)?;
+        let value = f(&a, &b).ok_or(Error::Overflow { pc: self.pc })?;
+        self.instruction_output(o, 3, value)?;
         self.pc += 4;
+        Ok(None)
+    }
+
+    // Decodes the opcode at pc, using the decode cache if possible.
+    fn decode(&mut self) -> Result<Opcode, Error> {
+        let (pc, raw) = (self.pc, &self.ram[self.pc]);
+        let cache = match self.decode_cache.as_mut() {
+            Some(x) if pc < DECODE_CACHE_LIMIT => x,
+            _ => return custom::parse(&self.custom, pc, word::saturate(raw)),
+        };
+
+        if pc >= cache.len() {
+            cache.resize(pc + 1, None);
+        }
+        match &cache[pc] {
+            Some((cached, opcode)) if cached == raw => Ok(*opcode),
+            _ => {
+                let opcode = custom::parse(&self.custom, pc, word::saturate(raw))?;
+                let raw = raw.clone();
+                cache[pc] = Some((raw, opcode));
+                Ok(opcode)
+            }
+        }
+    }
+
+    // Reads the values of every operand the instruction at pc reads from.
+    fn read_operands(&self, opcode: Opcode) -> Result<Vec<W>, Error> {
+        let output = opcode.output_operand();
+        opcode
+            .modes()
+            .into_iter()
+            .enumerate()
+            .filter(|&(i, _)| Some(i) != output)
+            .map(|(i, m)| self.lookup_param(m, i + 1))
+            .collect()
+    }
+
+    fn trace(&mut self, pc: usize, relative_base: i64, opcode: Opcode, operands: Vec<W>) {
+        let write = self.last_write.clone();
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(&trace::Event {
+                pc,
+                opcode,
+                operands,
+                write,
+                relative_base,
+            });
+        }
     }
 
     // Lookup an op parameter. Offset should start at 1.
-    fn lookup_param(&self, mode: ParameterMode, offset: usize) -> i64 {
+    fn lookup_param(&self, mode: ParameterMode, offset: usize) -> Result<W, Error> {
         let p = match mode {
             ParameterMode::Immediate => self.pc + offset,
-            ParameterMode::Position => self.ram[self.pc + offset] as usize,
-            ParameterMode::Relative => (self.relative_base + self.ram[self.pc + offset]) as usize,
+            ParameterMode::Position => self.address(&self.ram[self.pc + offset])?,
+            ParameterMode::Relative => self.relative_address(&self.ram[self.pc + offset])?,
         };
 
-        self.ram[p]
+        Ok(self.ram.get(p))
     }
 
     // Writes output of an instruction.
-    fn instruction_output(&mut self, mode: ParameterMode, offset: usize, value: i64) {
+    fn instruction_output(
+        &mut self,
+        mode: ParameterMode,
+        offset: usize,
+        value: W,
+    ) -> Result<(), Error> {
         let p = match mode {
-            ParameterMode::Position => self.ram[self.pc + offset],
-            ParameterMode::Relative => self.relative_base + self.ram[self.pc + offset],
-            ParameterMode::Immediate => panic!("opcode output cannot be immediate value"),
+            ParameterMode::Position => self.address(&self.ram[self.pc + offset])?,
+            ParameterMode::Relative => self.relative_address(&self.ram[self.pc + offset])?,
+            ParameterMode::Immediate => return Err(Error::ImmediateWrite { pc: self.pc }),
         };
 
-        self.ram[p as usize] = value;
+        if self.history.is_some() || self.code_watch.is_some() {
+            self.overwritten = Some((p, self.ram.get(p)));
+        }
+        if !self.ram.set(p, value.clone()) {
+            return Err(Error::AddressOutOfRange {
+                pc: self.pc,
+                address: p,
+            });
+        }
+        self.last_write = Some((p, value));
+
+        Ok(())
+    }
+
+    // Converts a raw value into a ram address. Values too large to be an
+    // address are reported as out of range.
+    fn address(&self, raw: &W) -> Result<usize, Error> {
+        match raw.to_i64() {
+            Some(x) if x >= 0 => Ok(x as usize),
+            _ if *raw < W::from_i64(0) => Err(Error::NegativeAddress {
+                pc: self.pc,
+                address: word::saturate(raw),
+            }),
+            _ => Err(Error::AddressOutOfRange {
+                pc: self.pc,
+                address: usize::MAX,
+            }),
+        }
+    }
+
+    // Converts an offset from the relative base into a ram address.
+    fn relative_address(&self, offset: &W) -> Result<usize, Error> {
+        let raw = W::from_i64(self.relative_base)
+            .checked_add(offset)
+            .ok_or(Error::Overflow { pc: self.pc })?;
+        self.address(&raw)
     }
 }
 
-#[derive(Debug)]
-enum ParameterMode {
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParameterMode {
     Position,
     Immediate,
     Relative,
 }
 
 impl ParameterMode {
-    fn new(m: u8) -> ParameterMode {
+    fn new(pc: usize, m: i64) -> Result<ParameterMode, Error> {
         match m {
-            0 => ParameterMode::Position,
-            1 => ParameterMode::Immediate,
-            2 => ParameterMode::Relative,
-            _ => panic!("bad ParameterMode"),
+            0 => Ok(ParameterMode::Position),
+            1 => Ok(ParameterMode::Immediate),
+            2 => Ok(ParameterMode::Relative),
+            _ => Err(Error::BadParameterMode { pc, mode: m }),
         }
     }
 }
 
-#[derive(Debug)]
-enum Opcode {
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Opcode {
     Add(ParameterMode, ParameterMode, ParameterMode),
     Mul(ParameterMode, ParameterMode, ParameterMode),
     Input(ParameterMode),
@@ -190,15 +578,72 @@ enum Opcode {
     Equals(ParameterMode, ParameterMode, ParameterMode),
     AdjustRelativeBase(ParameterMode),
     Halt,
+    // An opcode registered with Computer::register_opcode, with the modes
+    // of all three mode digits.
+    Custom(CustomOpcode, [ParameterMode; 3]),
 }
 
-fn parse_opcode(opcode: i64) -> Opcode {
+impl Opcode {
+    // Returns the parameter modes of the instruction in operand order.
+    pub fn modes(&self) -> Vec<ParameterMode> {
+        match *self {
+            Opcode::Add(m1, m2, m3)
+            | Opcode::Mul(m1, m2, m3)
+            | Opcode::LessThan(m1, m2, m3)
+            | Opcode::Equals(m1, m2, m3) => vec![m1, m2, m3],
+            Opcode::JumpIfTrue(m1, m2) | Opcode::JumpIfFalse(m1, m2) => vec![m1, m2],
+            Opcode::Input(m1) | Opcode::Output(m1) | Opcode::AdjustRelativeBase(m1) => vec![m1],
+            Opcode::Halt => vec![],
+            Opcode::Custom(c, modes) => modes[..c.arity].to_vec(),
+        }
+    }
+
+    // Returns the index of the operand the instruction writes to, if any.
+    pub fn output_operand(&self) -> Option<usize> {
+        match self {
+            Opcode::Add(..) | Opcode::Mul(..) | Opcode::LessThan(..) | Opcode::Equals(..) => {
+                Some(2)
+            }
+            Opcode::Input(..) => Some(0),
+            Opcode::Custom(c, _) => c.output,
+            _ => None,
+        }
+    }
+
+    // Number of cells the instruction occupies, including the opcode itself.
+    pub fn size(&self) -> usize {
+        self.modes().len() + 1
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Add(..) => "add",
+            Opcode::Mul(..) => "mul",
+            Opcode::Input(..) => "in",
+            Opcode::Output(..) => "out",
+            Opcode::JumpIfTrue(..) => "jt",
+            Opcode::JumpIfFalse(..) => "jf",
+            Opcode::LessThan(..) => "lt",
+            Opcode::Equals(..) => "eq",
+            Opcode::AdjustRelativeBase(..) => "arb",
+            Opcode::Halt => "hlt",
+            Opcode::Custom(c, _) => c.mnemonic,
+        }
+    }
+}
+
+// Decodes the opcode found at pc.
+fn parse_opcode(pc: usize, opcode: i64) -> Result<Opcode, Error> {
+    if opcode < 0 {
+        return Err(Error::BadOpcode { pc, opcode });
+    }
+
     let instruction = opcode % 100;
-    let m1 = ParameterMode::new((opcode / 100 % 10) as u8);
-    let m2 = ParameterMode::new((opcode / 100 / 10 % 10) as u8);
-    let m3 = ParameterMode::new((opcode / 100 / 10 / 10 % 10) as u8);
+    let m1 = ParameterMode::new(pc, opcode / 100 % 10)?;
+    let m2 = ParameterMode::new(pc, opcode / 100 / 10 % 10)?;
+    let m3 = ParameterMode::new(pc, opcode / 100 / 10 / 10 % 10)?;
 
-    match instruction {
+    let op = match instruction {
         1 => Opcode::Add(m1, m2, m3),
         2 => Opcode::Mul(m1, m2, m3),
         3 => Opcode::Input(m1),
@@ -209,8 +654,10 @@ fn parse_opcode(opcode: i64) -> Opcode {
         8 => Opcode::Equals(m1, m2, m3),
         9 => Opcode::AdjustRelativeBase(m1),
         99 => Opcode::Halt,
-        _ => panic!("bad Opcode"),
-    }
+        _ => return Err(Error::BadOpcode { pc, opcode }),
+    };
+
+    Ok(op)
 }
 
 #[cfg(test)]
@@ -220,8 +667,8 @@ mod tests {
     fn exec_test(input: Vec<i64>, expected: Vec<i64>) {
         let mem = input.clone();
         let mut cpu = Computer::new(&mem);
-        cpu.execute();
-        assert_eq!(cpu.ram[..expected.len()], expected[..]);
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+        assert_eq!(cpu.ram.range(0..expected.len()), expected);
     }
 
     #[test]
@@ -238,4 +685,83 @@ mod tests {
             vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50],
         );
     }
+
+    fn exec_error_test(input: &[i64], expected: Error) {
+        let mut cpu = Computer::new(input);
+        assert_eq!(cpu.execute(), Err(expected));
+    }
+
+    #[test]
+    fn test_execute_errors() {
+        exec_error_test(&[1, 0, 0, 0, 42], Error::BadOpcode { pc: 4, opcode: 42 });
+        exec_error_test(
+            &[301, 0, 0, 0, 99],
+            Error::BadParameterMode { pc: 0, mode: 3 },
+        );
+        exec_error_test(&[11101, 1, 1, 0, 99], Error::ImmediateWrite { pc: 0 });
+        exec_error_test(
+            &[1, -1, 0, 0, 99],
+            Error::NegativeAddress { pc: 0, address: -1 },
+        );
+    }
+
+    #[test]
+    fn test_memory() {
+        let mut cpu = Computer::new(&[1101, 2, 3, 1 << 40, 4, 1 << 40, 99]);
+        assert_eq!(cpu.execute(), Ok(State::Output(5)));
+        assert_eq!(cpu.ram[1 << 40], 5);
+
+        let mut cpu = Computer::with_memory_limit(&[1101, 2, 3, 100, 99], 50);
+        assert_eq!(
+            cpu.execute(),
+            Err(Error::AddressOutOfRange {
+                pc: 0,
+                address: 100
+            })
+        );
+    }
+
+    #[test]
+    fn test_budget() {
+        // Loops forever incrementing [7].
+        let mut cpu = Computer::new(&[1001, 7, 1, 7, 1105, 1, 0, 0]);
+        let budget = Budget {
+            instructions: Some(10),
+            deadline: None,
+        };
+        assert_eq!(cpu.execute_with_budget(budget), Ok(Budgeted::Exhausted));
+        assert_eq!(cpu.instruction_count(), 10);
+        assert_eq!(cpu.ram[7], 5);
+
+        assert_eq!(cpu.execute_with_budget(budget), Ok(Budgeted::Exhausted));
+        assert_eq!(cpu.instruction_count(), 20);
+        assert_eq!(cpu.pc(), 0);
+
+        let budget = Budget {
+            instructions: None,
+            deadline: Some(Instant::now()),
+        };
+        assert_eq!(cpu.execute_with_budget(budget), Ok(Budgeted::Exhausted));
+        assert_eq!(cpu.instruction_count(), 20);
+    }
+
+    #[test]
+    fn test_decode_cache() {
+        // Outputs [12], then overwrites its own out instruction with hlt and
+        // jumps back to it.
+        let program = [4, 12, 1101, 99, 0, 0, 1105, 1, 0, 0, 0, 0, 7];
+        for &enabled in [true, false].iter() {
+            let mut cpu = Computer::new(&program);
+            cpu.set_decode_cache(enabled);
+            assert_eq!(cpu.execute(), Ok(State::Output(7)));
+            assert_eq!(cpu.execute(), Ok(State::Halted));
+            assert_eq!(cpu.pc(), 0);
+        }
+    }
+
+    #[test]
+    fn test_input_not_requested() {
+        let mut cpu = Computer::new(&[99]);
+        assert_eq!(cpu.input(1), Err(Error::InputNotRequested { pc: 0 }));
+    }
 }
@@ -0,0 +1,116 @@
+use super::{Error, Machine, State};
+
+use std::fmt::Write as _;
+use std::io;
+use std::io::Write;
+
+// Outputs below this are characters. Anything else, such as a puzzle answer
+// at the end of a run, is a number.
+const ASCII_LIMIT: i64 = 128;
+
+// Character codes for text. A line should end with '\n', which is code 10.
+pub fn encode(text: &str) -> impl Iterator<Item = i64> + '_ {
+    text.bytes().map(i64::from)
+}
+
+// Renders output as text. Values that are not ASCII are written as numbers,
+// each on its own line.
+pub fn decode(values: &[i64]) -> String {
+    let mut out = String::new();
+    for &x in values {
+        if (0..ASCII_LIMIT).contains(&x) {
+            out.push(x as u8 as char);
+        } else {
+            writeln!(out, "{}", x).unwrap();
+        }
+    }
+    out
+}
+
+impl Machine {
+    // Queues text as input.
+    pub fn push_str(&mut self, text: &str) {
+        self.input.extend(encode(text));
+    }
+
+    // Removes all queued output and returns it decoded as text.
+    pub fn take_string(&mut self) -> String {
+        let text = decode(self.output.make_contiguous());
+        self.output.clear();
+        text
+    }
+}
+
+// Runs program on input until it halts or needs more input, and returns its
+// output as text.
+pub fn run_ascii(program: &[i64], input: &str) -> Result<String, Error> {
+    let mut machine = Machine::new(program);
+    machine.push_str(input);
+    machine.run()?;
+    Ok(machine.take_string())
+}
+
+// Runs a program that speaks ASCII on stdin and stdout. Each line typed is
+// sent followed by a newline. Stops when the program halts or stdin ends.
+pub fn execute_ascii_console_program(program: &[i64]) -> Result<(), Error> {
+    let mut machine = Machine::new(program);
+    loop {
+        let state = machine.run()?;
+        print!("{}", machine.take_string());
+        let _ = io::stdout().flush();
+        if state == State::Halted {
+            return Ok(());
+        }
+
+        let mut line = String::new();
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => return Ok(()),
+            Ok(_) => (),
+        }
+        if !line.ends_with('\n') {
+            line.push('\n');
+        }
+        machine.push_str(&line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    // Echoes a line without its newline, then outputs 1000 and halts.
+    const ECHO: &str = "
+        loop:
+            in [c]
+            eq [c], #10, [t]
+            jt [t], #done
+            out [c]
+            jt #1, #loop
+        done:
+            out #1000
+            hlt
+        c: db 0
+        t: db 0
+    ";
+
+    #[test]
+    fn test_encode_decode() {
+        assert_eq!(encode("A,1\n").collect::<Vec<_>>(), vec![65, 44, 49, 10]);
+        assert_eq!(decode(&[35, 46, 10, 12345, 35]), "#.\n12345\n#");
+    }
+
+    #[test]
+    fn test_run_ascii() {
+        let program = asm::assemble(ECHO).unwrap();
+        assert_eq!(run_ascii(&program, "hi\n"), Ok("hi1000\n".to_string()));
+
+        let mut machine = Machine::new(&program);
+        machine.push_str("abc");
+        assert_eq!(machine.run(), Ok(State::InputRequested));
+        assert_eq!(machine.take_string(), "abc");
+        machine.push_str("d\n");
+        assert_eq!(machine.run(), Ok(State::Halted));
+        assert_eq!(machine.take_string(), "d1000\n");
+    }
+}
@@ -0,0 +1,411 @@
+use super::ParameterMode;
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+// Assembles source into a program for Computer::new.
+//
+// Each line holds an optional label, then an instruction, a data directive or
+// a constant definition. Comments start with ';'.
+//
+//         arb #stack+1        ; set up the relative base
+//     ret = -1                ; constants can name relative offsets
+//     loop:
+//         in rel[ret]
+//         out rel[ret]
+//         jt #1, #loop
+//     stack: db 0, 0, 0
+//
+// Operands are written [x] for position mode, #x for immediate mode and
+// rel[x] for relative mode, where x is a number, a symbol, or a sum such as
+// table+2. Mnemonics are the ones printed by disasm (add, mul, in, out, jt,
+// jf, lt, eq, arb, hlt) or the lowercase Opcode variant names.
+pub fn assemble(source: &str) -> Result<Vec<i64>, Error> {
+    let mut symbols = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+
+    for (i, raw) in source.lines().enumerate() {
+        let line = i + 1;
+        let mut text = raw.split(';').next().unwrap().trim();
+
+        while let Some((label, rest)) = split_label(text) {
+            define(&mut symbols, label, Symbol::Address(address as i64), line)?;
+            text = rest;
+        }
+
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some((name, expr)) = split_constant(text) {
+            define(&mut symbols, name, Symbol::Expr(expr.to_string()), line)?;
+            continue;
+        }
+
+        let (word, rest) = match text.find(char::is_whitespace) {
+            Some(i) => (&text[..i], text[i..].trim()),
+            None => (text, ""),
+        };
+        let args: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(|x| x.trim()).collect()
+        };
+
+        let kind = if word == "db" {
+            if args.is_empty() {
+                return Err(Error::new(line, "db needs at least one value"));
+            }
+            Kind::Data(args.iter().map(|x| x.to_string()).collect())
+        } else {
+            let info = match lookup_mnemonic(word) {
+                Some(x) => x,
+                None => return Err(Error::new(line, &format!("unknown mnemonic {}", word))),
+            };
+            if args.len() != info.arity {
+                return Err(Error::new(
+                    line,
+                    &format!("{} takes {} operands, got {}", word, info.arity, args.len()),
+                ));
+            }
+            let operands = args
+                .iter()
+                .map(|x| parse_operand(x, line))
+                .collect::<Result<Vec<_>, _>>()?;
+            if let Some(i) = info.output {
+                if operands[i].0 == ParameterMode::Immediate {
+                    return Err(Error::new(line, "output operand cannot be immediate"));
+                }
+            }
+            Kind::Instruction(info.code, operands)
+        };
+
+        address += match &kind {
+            Kind::Data(values) => values.len(),
+            Kind::Instruction(_, operands) => operands.len() + 1,
+        };
+        statements.push(Statement { line, kind });
+    }
+
+    let mut program = Vec::with_capacity(address);
+    for statement in statements.iter() {
+        let line = statement.line;
+        match &statement.kind {
+            Kind::Data(values) => {
+                for v in values.iter() {
+                    program.push(evaluate(&symbols, v, line, 0)?);
+                }
+            }
+            Kind::Instruction(code, operands) => {
+                let modes = operands
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (m, _))| mode_digit(*m) * 10i64.pow(i as u32 + 2))
+                    .sum::<i64>();
+                program.push(code + modes);
+                for (_, expr) in operands.iter() {
+                    program.push(evaluate(&symbols, expr, line, 0)?);
+                }
+            }
+        }
+    }
+
+    Ok(program)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Error {
+    fn new(line: usize, message: &str) -> Error {
+        Error {
+            line,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl error::Error for Error {}
+
+struct Statement {
+    line: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    Instruction(i64, Vec<(ParameterMode, String)>),
+    Data(Vec<String>),
+}
+
+enum Symbol {
+    Address(i64),
+    Expr(String),
+}
+
+struct MnemonicInfo {
+    code: i64,
+    arity: usize,
+    // Index of the operand the instruction writes to, if any.
+    output: Option<usize>,
+}
+
+fn lookup_mnemonic(word: &str) -> Option<MnemonicInfo> {
+    let (code, arity, output) = match word {
+        "add" => (1, 3, Some(2)),
+        "mul" => (2, 3, Some(2)),
+        "in" | "input" => (3, 1, Some(0)),
+        "out" | "output" => (4, 1, None),
+        "jt" | "jumpiftrue" => (5, 2, None),
+        "jf" | "jumpiffalse" => (6, 2, None),
+        "lt" | "lessthan" => (7, 3, Some(2)),
+        "eq" | "equals" => (8, 3, Some(2)),
+        "arb" | "adjustrelativebase" => (9, 1, None),
+        "hlt" | "halt" => (99, 0, None),
+        _ => return None,
+    };
+
+    Some(MnemonicInfo {
+        code,
+        arity,
+        output,
+    })
+}
+
+fn mode_digit(mode: ParameterMode) -> i64 {
+    match mode {
+        ParameterMode::Position => 0,
+        ParameterMode::Immediate => 1,
+        ParameterMode::Relative => 2,
+    }
+}
+
+fn parse_operand(text: &str, line: usize) -> Result<(ParameterMode, String), Error> {
+    let (mode, expr) = if let Some(x) = text.strip_prefix('#') {
+        (ParameterMode::Immediate, x)
+    } else if let Some(x) = text.strip_prefix("rel[").and_then(|x| x.strip_suffix(']')) {
+        (ParameterMode::Relative, x)
+    } else if let Some(x) = text.strip_prefix('[').and_then(|x| x.strip_suffix(']')) {
+        (ParameterMode::Position, x)
+    } else {
+        return Err(Error::new(
+            line,
+            &format!("bad operand {} (expected [x], #x or rel[x])", text),
+        ));
+    };
+
+    Ok((mode, expr.trim().to_string()))
+}
+
+// Splits "name: rest" into its label and the rest of the line.
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let i = text.find(':')?;
+    let label = text[..i].trim();
+    if is_identifier(label) {
+        Some((label, text[i + 1..].trim()))
+    } else {
+        None
+    }
+}
+
+// Splits "name = expr" into its name and expression.
+fn split_constant(text: &str) -> Option<(&str, &str)> {
+    let i = text.find('=')?;
+    let name = text[..i].trim();
+    if is_identifier(name) {
+        Some((name, text[i + 1..].trim()))
+    } else {
+        None
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn define(
+    symbols: &mut HashMap<String, Symbol>,
+    name: &str,
+    symbol: Symbol,
+    line: usize,
+) -> Result<(), Error> {
+    if lookup_mnemonic(name).is_some() || name == "db" {
+        return Err(Error::new(line, &format!("{} is a reserved word", name)));
+    }
+    if symbols.insert(name.to_string(), symbol).is_some() {
+        return Err(Error::new(line, &format!("{} defined twice", name)));
+    }
+    Ok(())
+}
+
+// Evaluates a sum of numbers and symbols such as "table+2" or "-x-1". depth
+// guards against constants that refer to themselves.
+fn evaluate(
+    symbols: &HashMap<String, Symbol>,
+    expr: &str,
+    line: usize,
+    depth: usize,
+) -> Result<i64, Error> {
+    if depth > 32 {
+        return Err(Error::new(line, "constant definitions are circular"));
+    }
+
+    let expr: String = expr.chars().filter(|c| !c.is_whitespace()).collect();
+    if expr.is_empty() {
+        return Err(Error::new(line, "missing value"));
+    }
+
+    // Adds sign * term to total.
+    let add = |total: i64, sign: i64, term: &str| -> Result<i64, Error> {
+        let value = evaluate_term(symbols, term, line, depth)?;
+        sign.checked_mul(value)
+            .and_then(|x| total.checked_add(x))
+            .ok_or_else(|| Error::new(line, "value out of range"))
+    };
+
+    let mut total = 0;
+    let mut sign = 1;
+    let mut term = String::new();
+    for c in expr.chars() {
+        if c == '+' || c == '-' {
+            if !term.is_empty() {
+                total = add(total, sign, &term)?;
+                term.clear();
+                sign = 1;
+            }
+            if c == '-' {
+                sign = -sign;
+            }
+        } else {
+            term.push(c);
+        }
+    }
+
+    if term.is_empty() {
+        return Err(Error::new(line, &format!("bad expression {}", expr)));
+    }
+
+    add(total, sign, &term)
+}
+
+fn evaluate_term(
+    symbols: &HashMap<String, Symbol>,
+    term: &str,
+    line: usize,
+    depth: usize,
+) -> Result<i64, Error> {
+    if let Ok(x) = term.parse() {
+        return Ok(x);
+    }
+
+    match symbols.get(term) {
+        Some(Symbol::Address(x)) => Ok(*x),
+        Some(Symbol::Expr(e)) => evaluate(symbols, e, line, depth + 1),
+        None if is_identifier(term) => Err(Error::new(line, &format!("undefined symbol {}", term))),
+        None => Err(Error::new(line, &format!("bad value {}", term))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{disasm, Computer, State};
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+            add [a], [b], [3]   ; the day 2 example
+            mul [3], [c], [0]
+            hlt
+        a:  db 30
+        b:  db 40
+        c:  db 50
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50])
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let program = vec![21101, 10, -5, 3, 1005, 10, 0, 204, -1, 9, 4, 3, 0, 99];
+        let source = disasm::disassemble(&program)
+            .iter()
+            .map(|x| match x {
+                disasm::Line::Code(inst) => inst.to_string(),
+                disasm::Line::Data { value, .. } => format!("db {}", value),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(assemble(&source), Ok(program));
+    }
+
+    #[test]
+    fn test_labels_and_constants() {
+        let source = "
+                arb #stack+1
+            ret = -1
+            top = ret + 1
+            loop:
+                in rel[ret]
+                add rel[ret], #1, rel[top]
+                out rel[top]
+                jt #1, #loop
+            stack: db 0, 0, stack+1
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program[..4], [109, 14, 203, -1]);
+        assert_eq!(program[4], 21201);
+        assert_eq!(program[10..], [1105, 1, 2, 0, 0, 14]);
+
+        let mut cpu = Computer::new(&program);
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        cpu.input(41).unwrap();
+        assert_eq!(cpu.execute(), Ok(State::Output(42)));
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |source: &str| assemble(source).unwrap_err().to_string();
+        assert_eq!(err("hlt\nfoo [1]"), "line 2: unknown mnemonic foo");
+        assert_eq!(err("add [1], [2]"), "line 1: add takes 3 operands, got 2");
+        assert_eq!(
+            err("add [1], [2], #3"),
+            "line 1: output operand cannot be immediate"
+        );
+        assert_eq!(
+            err("out 5"),
+            "line 1: bad operand 5 (expected [x], #x or rel[x])"
+        );
+        assert_eq!(err("\n\nout [nowhere]"), "line 3: undefined symbol nowhere");
+        assert_eq!(err("a: hlt\na: hlt"), "line 2: a defined twice");
+        assert_eq!(
+            err("x = y\ny = x\nout #x"),
+            "line 3: constant definitions are circular"
+        );
+        assert_eq!(
+            err("db 9223372036854775807+1"),
+            "line 1: value out of range"
+        );
+        assert_eq!(
+            err("db -9223372036854775807-2"),
+            "line 1: value out of range"
+        );
+    }
+}
@@ -0,0 +1,410 @@
+use super::disasm::{self, Instruction, Line, Operand};
+use super::Opcode;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+// How control leaves a basic block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Exit {
+    // Runs on into the block at the given address.
+    Fall(usize),
+    Jump(usize),
+    // Jumps to taken if cond is nonzero (jt) or zero (jf), otherwise runs on
+    // into fall.
+    Branch {
+        cond: Operand,
+        if_true: bool,
+        taken: usize,
+        fall: usize,
+    },
+    // Pushes ret onto the stack at rel[0] and jumps to target.
+    Call {
+        target: usize,
+        ret: usize,
+    },
+    // A call through a function pointer.
+    IndirectCall {
+        target: Operand,
+        ret: usize,
+    },
+    // Jumps to the address at rel[0].
+    Return,
+    // A jump to a computed address. Conditional ones run on into fall when
+    // not taken.
+    Indirect {
+        cond: Option<(Operand, bool)>,
+        target: Operand,
+        fall: Option<usize>,
+    },
+    Halt,
+}
+
+impl Exit {
+    // Addresses control can reach within the same function. A call
+    // continues at its return address.
+    pub fn successors(&self) -> Vec<usize> {
+        match *self {
+            Exit::Fall(x) | Exit::Jump(x) => vec![x],
+            Exit::Branch { taken, fall, .. } => vec![taken, fall],
+            Exit::Call { ret, .. } | Exit::IndirectCall { ret, .. } => vec![ret],
+            Exit::Indirect { fall, .. } => fall.into_iter().collect(),
+            Exit::Return | Exit::Halt => vec![],
+        }
+    }
+}
+
+// A run of instructions entered only at the top and left only at the
+// bottom.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    // Every instruction in the block, including the jump that ends it.
+    pub instructions: Vec<Instruction>,
+    pub exit: Exit,
+}
+
+impl Block {
+    // Address just past the last instruction.
+    pub fn end(&self) -> usize {
+        let last = self.instructions.last().unwrap();
+        last.address + last.size()
+    }
+}
+
+// Code reached from address 0 or from a call, along with the size of its
+// stack frame: the n of an arb #n it starts with, or 0.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    pub entry: usize,
+    pub frame: i64,
+    // Start of each block in the function, in address order.
+    pub blocks: Vec<usize>,
+}
+
+// The control-flow graph of the code disasm finds in a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+    // The function at address 0 comes first, then the others by address.
+    pub functions: Vec<Function>,
+}
+
+impl Cfg {
+    pub fn new(program: &[i64]) -> Cfg {
+        let code: Vec<Instruction> = disasm::disassemble(program)
+            .into_iter()
+            .filter_map(|line| match line {
+                Line::Code(inst) => Some(inst),
+                Line::Data { .. } => None,
+            })
+            .collect();
+
+        let targets: BTreeSet<usize> = code.iter().filter_map(|x| x.jump_target()).collect();
+        let mut blocks = BTreeMap::new();
+        let mut current: Vec<Instruction> = Vec::new();
+        for inst in code {
+            let joins = match current.last() {
+                Some(prev) => prev.address + prev.size() == inst.address,
+                None => false,
+            };
+            if !joins || targets.contains(&inst.address) {
+                if let Some(block) = finish_block(&mut current) {
+                    blocks.insert(block.start, block);
+                }
+            }
+
+            let ends = is_jump(&inst) || inst.opcode == Opcode::Halt;
+            current.push(inst);
+            if ends {
+                let block = finish_block(&mut current).unwrap();
+                blocks.insert(block.start, block);
+            }
+        }
+        if let Some(block) = finish_block(&mut current) {
+            blocks.insert(block.start, block);
+        }
+
+        let mut entries: BTreeSet<usize> = blocks
+            .values()
+            .filter_map(|block| match block.exit {
+                Exit::Call { target, .. } => Some(target),
+                _ => None,
+            })
+            .collect();
+        entries.remove(&0);
+        let functions = std::iter::once(0)
+            .chain(entries)
+            .filter(|x| blocks.contains_key(x))
+            .map(|entry| function(&blocks, entry))
+            .collect();
+
+        Cfg { blocks, functions }
+    }
+
+    pub fn function(&self, entry: usize) -> Option<&Function> {
+        self.functions.iter().find(|f| f.entry == entry)
+    }
+
+    // Renders the graph in Graphviz DOT format, with each block's
+    // disassembly as its label. Function entries are drawn with a double
+    // border and calls as dashed edges.
+    pub fn dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph cfg {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            let mut attributes = String::new();
+            if let Some(function) = self.function(block.start) {
+                write!(label, "{}:\\l", name(function.entry)).unwrap();
+                attributes.push_str(", peripheries=2");
+            }
+            for inst in &block.instructions {
+                write!(label, "{}: {}\\l", inst.address, inst).unwrap();
+            }
+            writeln!(
+                out,
+                "    b{} [label=\"{}\"{}];",
+                block.start, label, attributes
+            )
+            .unwrap();
+        }
+
+        for block in self.blocks.values() {
+            let mut edge = |target: usize, attributes: &str| {
+                if self.blocks.contains_key(&target) {
+                    writeln!(out, "    b{} -> b{}{};", block.start, target, attributes).unwrap();
+                }
+            };
+            match block.exit {
+                Exit::Fall(target) | Exit::Jump(target) => edge(target, ""),
+                Exit::Branch { taken, fall, .. } => {
+                    edge(taken, " [label=\"taken\"]");
+                    edge(fall, " [label=\"fall\"]");
+                }
+                Exit::Call { target, ret } => {
+                    edge(target, " [style=dashed, label=\"call\"]");
+                    edge(ret, " [label=\"ret\"]");
+                }
+                Exit::IndirectCall { ret, .. } => edge(ret, " [label=\"ret\"]"),
+                Exit::Indirect {
+                    fall: Some(fall), ..
+                } => edge(fall, " [label=\"fall\"]"),
+                Exit::Indirect { fall: None, .. } | Exit::Return | Exit::Halt => (),
+            }
+        }
+
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+// The name decompile and dot give the function at entry.
+pub(super) fn name(entry: usize) -> String {
+    if entry == 0 {
+        "main".to_string()
+    } else {
+        format!("f{}", entry)
+    }
+}
+
+pub(super) fn is_jump(inst: &Instruction) -> bool {
+    matches!(
+        inst.opcode,
+        Opcode::JumpIfTrue(..) | Opcode::JumpIfFalse(..)
+    )
+}
+
+// Turns the instructions collected so far into a block.
+fn finish_block(instructions: &mut Vec<Instruction>) -> Option<Block> {
+    if instructions.is_empty() {
+        return None;
+    }
+    let instructions = std::mem::take(instructions);
+    let last = instructions.last().unwrap();
+    let end = last.address + last.size();
+
+    let exit = match last.opcode {
+        Opcode::Halt => Exit::Halt,
+        Opcode::JumpIfTrue(..) | Opcode::JumpIfFalse(..) => {
+            let if_true = matches!(last.opcode, Opcode::JumpIfTrue(..));
+            let operands = last.operands();
+            let (cond, target) = (operands[0], operands[1]);
+            let prev = instructions.len().checked_sub(2).map(|i| &instructions[i]);
+            let call = prev.and_then(pushed_return_address) == Some(end);
+            match (cond, last.jump_target()) {
+                (Operand::Immediate(x), _) if (x != 0) != if_true => Exit::Fall(end),
+                (Operand::Immediate(_), Some(target)) if call => Exit::Call { target, ret: end },
+                (Operand::Immediate(_), Some(target)) => Exit::Jump(target),
+                (Operand::Immediate(_), None) if target == Operand::Relative(0) => Exit::Return,
+                (Operand::Immediate(_), None) if call => Exit::IndirectCall { target, ret: end },
+                (Operand::Immediate(_), None) => Exit::Indirect {
+                    cond: None,
+                    target,
+                    fall: None,
+                },
+                (_, Some(taken)) => Exit::Branch {
+                    cond,
+                    if_true,
+                    taken,
+                    fall: end,
+                },
+                (_, None) => Exit::Indirect {
+                    cond: Some((cond, if_true)),
+                    target,
+                    fall: Some(end),
+                },
+            }
+        }
+        _ => Exit::Fall(end),
+    };
+
+    Some(Block {
+        start: instructions[0].address,
+        instructions,
+        exit,
+    })
+}
+
+// Recognizes the first half of a call: a return address stored at rel[0],
+// the top of the caller's stack.
+fn pushed_return_address(inst: &Instruction) -> Option<usize> {
+    match inst.operands().get(2) {
+        Some(Operand::Relative(0)) => disasm::return_address(inst),
+        _ => None,
+    }
+}
+
+// Collects the blocks reachable from entry without following calls.
+fn function(blocks: &BTreeMap<usize, Block>, entry: usize) -> Function {
+    let mut seen = BTreeSet::new();
+    let mut work = vec![entry];
+    while let Some(address) = work.pop() {
+        if let Some(block) = blocks.get(&address) {
+            if seen.insert(address) {
+                work.extend(block.exit.successors());
+            }
+        }
+    }
+
+    let first = &blocks[&entry].instructions[0];
+    let frame = match (first.opcode, first.operands().first()) {
+        (Opcode::AdjustRelativeBase(_), Some(&Operand::Immediate(n))) => n.max(0),
+        _ => 0,
+    };
+
+    Function {
+        entry,
+        frame,
+        blocks: seen.into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    // Reads n and outputs n + n via a function that doubles its argument.
+    const DOUBLE: &str = "
+            arb #stack
+            in rel[1]
+            add #ret, #0, rel[0]
+            jt #1, #double
+        ret:
+            out rel[1]
+            hlt
+        double:
+            arb #2
+            add rel[-1], rel[-1], rel[-1]
+            arb #-2
+            jt #1, rel[0]
+        stack: db 0
+    ";
+
+    #[test]
+    fn test_blocks() {
+        let program = asm::assemble(DOUBLE).unwrap();
+        let cfg = Cfg::new(&program);
+        let starts: Vec<_> = cfg.blocks.keys().cloned().collect();
+        assert_eq!(starts, vec![0, 11, 14]);
+
+        assert_eq!(
+            cfg.blocks[&0].exit,
+            Exit::Call {
+                target: 14,
+                ret: 11
+            }
+        );
+        assert_eq!(cfg.blocks[&0].end(), 11);
+        assert_eq!(cfg.blocks[&11].exit, Exit::Halt);
+        assert_eq!(cfg.blocks[&14].exit, Exit::Return);
+    }
+
+    #[test]
+    fn test_functions() {
+        let program = asm::assemble(DOUBLE).unwrap();
+        let cfg = Cfg::new(&program);
+        assert_eq!(
+            cfg.functions,
+            vec![
+                Function {
+                    entry: 0,
+                    frame: 25,
+                    blocks: vec![0, 11],
+                },
+                Function {
+                    entry: 14,
+                    frame: 2,
+                    blocks: vec![14],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let program = asm::assemble(DOUBLE).unwrap();
+        let expected = r#"digraph cfg {
+    node [shape=box, fontname="monospace"];
+    b0 [label="main:\l0: arb #25\l2: in rel[1]\l4: add #11, #0, rel[0]\l8: jt #1, #14\l", peripheries=2];
+    b11 [label="11: out rel[1]\l13: hlt\l"];
+    b14 [label="f14:\l14: arb #2\l16: add rel[-1], rel[-1], rel[-1]\l20: arb #-2\l22: jt #1, rel[0]\l", peripheries=2];
+    b0 -> b14 [style=dashed, label="call"];
+    b0 -> b11 [label="ret"];
+}
+"#;
+        assert_eq!(Cfg::new(&program).dot(), expected);
+    }
+
+    #[test]
+    fn test_branches() {
+        // Outputs 1 if the input is nonzero, then loops back for more.
+        let program = asm::assemble(
+            "
+            loop:
+                in [x]
+                jf [x], #loop
+                out #1
+                jt #0, #loop
+                jf #0, #loop
+            x:  db 0
+            ",
+        )
+        .unwrap();
+        let cfg = Cfg::new(&program);
+        assert_eq!(
+            cfg.blocks[&0].exit,
+            Exit::Branch {
+                cond: Operand::Position(13),
+                if_true: false,
+                taken: 0,
+                fall: 5,
+            }
+        );
+        assert_eq!(cfg.blocks[&5].exit, Exit::Fall(10));
+        assert_eq!(cfg.blocks[&10].exit, Exit::Jump(0));
+    }
+}
@@ -0,0 +1,306 @@
+use super::{Computer, Error, State};
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How long a machine waiting for input sleeps between checks for whether
+// every machine is waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// A set of machines that each run on their own thread, connected by
+// channels. Every output of a machine is sent to each machine it is
+// connected to, in the order the connections were made.
+//
+// A machine stops when it halts, fails, or requests input after every
+// machine that could send it input has stopped. Once every machine still
+// going is waiting for input and no values are in flight, for example in a
+// cycle where each waits on the next, they all stop.
+#[derive(Default)]
+pub struct Cluster {
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    cpu: Computer,
+    // Input queued before the cluster starts.
+    inputs: Vec<i64>,
+    targets: Vec<usize>,
+}
+
+// Shared by the machines of a running cluster to tell when all of them are
+// waiting for input that can never come.
+struct Activity {
+    // Machines that are executing rather than waiting for input.
+    running: usize,
+    // Values sent but not yet received.
+    in_flight: usize,
+    stopped: Vec<bool>,
+}
+
+// A machine after the cluster has run.
+pub struct Finished {
+    pub cpu: Computer,
+    // Halted, or InputRequested if the machine ran out of input.
+    pub state: State,
+    // Everything the machine output, in order.
+    pub outputs: Vec<i64>,
+}
+
+impl Cluster {
+    pub fn new() -> Cluster {
+        Cluster::default()
+    }
+
+    // Machines where each one's output feeds the next.
+    pub fn chain(cpus: Vec<Computer>) -> Cluster {
+        let mut cluster = Cluster::new();
+        let n = cpus.len();
+        for cpu in cpus {
+            cluster.add(cpu);
+        }
+        for i in 1..n {
+            cluster.connect(i - 1, i);
+        }
+        cluster
+    }
+
+    // A chain whose last machine also feeds the first.
+    pub fn ring(cpus: Vec<Computer>) -> Cluster {
+        let n = cpus.len();
+        let mut cluster = Cluster::chain(cpus);
+        if n > 0 {
+            cluster.connect(n - 1, 0);
+        }
+        cluster
+    }
+
+    // Adds a machine. Returns its index, which is also its position in the
+    // results of run.
+    pub fn add(&mut self, cpu: Computer) -> usize {
+        self.nodes.push(Node {
+            cpu,
+            inputs: Vec::new(),
+            targets: Vec::new(),
+        });
+        self.nodes.len() - 1
+    }
+
+    // Sends the output of machine from to machine to.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        assert!(to < self.nodes.len(), "no machine {}", to);
+        self.nodes[from].targets.push(to);
+    }
+
+    // Queues input for a machine ahead of anything other machines send it.
+    pub fn push_input(&mut self, node: usize, value: i64) {
+        self.nodes[node].inputs.push(value);
+    }
+
+    // Runs every machine to completion and returns how each one finished.
+    pub fn run(self) -> Vec<Result<Finished, Error>> {
+        let (senders, receivers): (Vec<Sender<i64>>, Vec<Receiver<i64>>) =
+            self.nodes.iter().map(|_| mpsc::channel()).unzip();
+
+        for (node, sender) in self.nodes.iter().zip(senders.iter()) {
+            for &x in node.inputs.iter() {
+                sender.send(x).unwrap();
+            }
+        }
+
+        let activity = Arc::new(Mutex::new(Activity {
+            running: self.nodes.len(),
+            in_flight: self.nodes.iter().map(|n| n.inputs.len()).sum(),
+            stopped: vec![false; self.nodes.len()],
+        }));
+        let handles: Vec<_> = self
+            .nodes
+            .into_iter()
+            .zip(receivers)
+            .enumerate()
+            .map(|(i, (node, input))| {
+                let targets = node
+                    .targets
+                    .iter()
+                    .map(|&i| (i, senders[i].clone()))
+                    .collect();
+                let activity = activity.clone();
+                thread::spawn(move || run_node(i, node.cpu, input, targets, activity))
+            })
+            .collect();
+
+        // Only the machines hold senders now, so a machine sees its input
+        // close once everything feeding it has stopped.
+        drop(senders);
+
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("machine thread panicked"))
+            .collect()
+    }
+}
+
+fn run_node(
+    index: usize,
+    mut cpu: Computer,
+    input: Receiver<i64>,
+    targets: Vec<(usize, Sender<i64>)>,
+    activity: Arc<Mutex<Activity>>,
+) -> Result<Finished, Error> {
+    let mut outputs = Vec::new();
+    let mut running = true;
+    let result = loop {
+        match cpu.execute() {
+            Ok(State::InputRequested) => match wait(&input, &activity) {
+                Some(x) => {
+                    if let Err(e) = cpu.input(x) {
+                        break Err(e);
+                    }
+                }
+                None => {
+                    running = false;
+                    break Ok(State::InputRequested);
+                }
+            },
+            Ok(State::Output(x)) => {
+                // A target that has already stopped just misses the value.
+                let mut activity = activity.lock().unwrap();
+                for (i, target) in targets.iter() {
+                    if !activity.stopped[*i] && target.send(x).is_ok() {
+                        activity.in_flight += 1;
+                    }
+                }
+                outputs.push(x);
+            }
+            result => break result,
+        }
+    };
+
+    // Values that arrive from now on are never read, so they no longer
+    // count as in flight. Sends check stopped under the same lock.
+    let mut activity = activity.lock().unwrap();
+    activity.stopped[index] = true;
+    activity.in_flight -= input.try_iter().count();
+    if running {
+        activity.running -= 1;
+    }
+
+    result.map(|state| Finished {
+        cpu,
+        state,
+        outputs,
+    })
+}
+
+// Waits for a value while counted as not running. Returns None once nothing
+// can send one: everything feeding this machine has stopped, or every
+// machine is waiting and no values are in flight.
+fn wait(input: &Receiver<i64>, activity: &Mutex<Activity>) -> Option<i64> {
+    activity.lock().unwrap().running -= 1;
+    loop {
+        match input.recv_timeout(POLL_INTERVAL) {
+            Ok(x) => {
+                let mut activity = activity.lock().unwrap();
+                activity.running += 1;
+                activity.in_flight -= 1;
+                return Some(x);
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let activity = activity.lock().unwrap();
+                if activity.running == 0 && activity.in_flight == 0 {
+                    return None;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Outputs each input plus 1, forever.
+    const INCREMENT: [i64; 12] = [3, 11, 1001, 11, 1, 11, 4, 11, 1105, 1, 0, 0];
+
+    // Reads a count and a value, then passes value+1 on count times.
+    const RELAY: [i64; 21] = [
+        3, 19, 3, 20, 1001, 20, 1, 20, 4, 20, 1001, 19, -1, 19, 1005, 19, 2, 99, 0, 0, 0,
+    ];
+
+    #[test]
+    fn test_chain() {
+        let cpus = (0..3).map(|_| Computer::new(&INCREMENT)).collect();
+        let mut cluster = Cluster::chain(cpus);
+        cluster.push_input(0, 10);
+        cluster.push_input(0, 20);
+
+        let results = cluster.run();
+        assert_eq!(results.len(), 3);
+        for (i, result) in results.into_iter().enumerate() {
+            let finished = result.unwrap();
+            assert_eq!(finished.state, State::InputRequested);
+            assert_eq!(finished.outputs, vec![11 + i as i64, 21 + i as i64]);
+        }
+    }
+
+    #[test]
+    fn test_ring() {
+        let cpus = (0..3).map(|_| Computer::new(&RELAY)).collect();
+        let mut cluster = Cluster::ring(cpus);
+        for i in 0..3 {
+            cluster.push_input(i, 2);
+        }
+        cluster.push_input(0, 0);
+
+        let outputs: Vec<_> = cluster
+            .run()
+            .into_iter()
+            .map(|r| r.unwrap().outputs)
+            .collect();
+        assert_eq!(outputs, vec![vec![1, 4], vec![2, 5], vec![3, 6]]);
+    }
+
+    #[test]
+    fn test_graph() {
+        // 0 feeds both 1 and 2, which both feed 3.
+        let mut cluster = Cluster::new();
+        for _ in 0..4 {
+            cluster.add(Computer::new(&INCREMENT));
+        }
+        cluster.connect(0, 1);
+        cluster.connect(0, 2);
+        cluster.connect(1, 3);
+        cluster.connect(2, 3);
+        cluster.push_input(0, 0);
+
+        let mut results = cluster.run();
+        let last = results.pop().unwrap().unwrap();
+        assert_eq!(last.outputs, vec![3, 3]);
+    }
+
+    #[test]
+    fn test_error() {
+        let mut cluster = Cluster::chain(vec![Computer::new(&INCREMENT), Computer::new(&[42])]);
+        cluster.push_input(0, 1);
+        let results = cluster.run();
+        assert_eq!(results[0].as_ref().unwrap().outputs, vec![2]);
+        assert_eq!(
+            results[1].as_ref().err(),
+            Some(&Error::BadOpcode { pc: 0, opcode: 42 })
+        );
+    }
+
+    #[test]
+    fn test_deadlock() {
+        // Each machine echoes what it reads, and neither is given anything.
+        let echo = [3, 7, 4, 7, 1105, 1, 0, 0];
+        let cluster = Cluster::ring(vec![Computer::new(&echo), Computer::new(&echo)]);
+        for result in cluster.run() {
+            let finished = result.unwrap();
+            assert_eq!(finished.state, State::InputRequested);
+            assert!(finished.outputs.is_empty());
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use super::disasm::{self, Instruction, Line};
+use super::{Computer, Error, Memory, Opcode, ParameterMode, State};
+
+use std::rc::Rc;
+
+// A compiled instruction. Operands are bound when the program is compiled, so
+// running one never looks at the instruction's cells. Returns the state if the
+// instruction produced output or halted.
+type Op = Box<dyn Fn(&mut Computer) -> Result<Option<State>, Error>>;
+
+// A program translated ahead of time into one closure per instruction found
+// by disassembly. Compile once and create any number of machines from it with
+// CompiledComputer::new.
+pub struct Compiled {
+    program: Vec<i64>,
+    // Compiled instruction starting at each address, if any.
+    ops: Vec<Option<Op>>,
+    // For each cell, the address of the compiled instruction it belongs to.
+    owners: Vec<Option<usize>>,
+}
+
+impl Compiled {
+    pub fn new(program: &[i64]) -> Compiled {
+        let mut ops: Vec<Option<Op>> = program.iter().map(|_| None).collect();
+        let mut owners = vec![None; program.len()];
+
+        for line in disasm::disassemble(program) {
+            if let Line::Code(inst) = line {
+                if let Some(op) = compile(&inst) {
+                    let end = inst.address + inst.size();
+                    owners[inst.address..end]
+                        .iter_mut()
+                        .for_each(|x| *x = Some(inst.address));
+                    ops[inst.address] = Some(op);
+                }
+            }
+        }
+
+        Compiled {
+            program: program.to_vec(),
+            ops,
+            owners,
+        }
+    }
+
+    // Number of instructions that were compiled.
+    pub fn len(&self) -> usize {
+        self.ops.iter().filter(|x| x.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// A Computer that runs compiled instructions where it can and falls back to
+// the interpreter elsewhere. Writing to a cell of a compiled instruction,
+// whether by the program or by poke, discards that instruction for this
+// machine and it is interpreted from then on.
+//
+// Tracers, history and profiling only see interpreted instructions.
+pub struct CompiledComputer {
+    compiled: Rc<Compiled>,
+    cpu: Computer,
+    // Whether the compiled instruction at each address is still usable.
+    valid: Vec<bool>,
+}
+
+impl CompiledComputer {
+    pub fn new(compiled: Rc<Compiled>) -> CompiledComputer {
+        let cpu = Computer::new(&compiled.program);
+        let valid = compiled.ops.iter().map(|x| x.is_some()).collect();
+        CompiledComputer {
+            compiled,
+            cpu,
+            valid,
+        }
+    }
+
+    pub fn ram(&self) -> &Memory {
+        &self.cpu.ram
+    }
+
+    pub fn pc(&self) -> usize {
+        self.cpu.pc()
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.cpu.relative_base()
+    }
+
+    pub fn instruction_count(&self) -> u64 {
+        self.cpu.instruction_count()
+    }
+
+    // Writes ram[address]. Returns false if the write would exceed the
+    // memory limit.
+    pub fn poke(&mut self, address: usize, value: i64) -> bool {
+        if !self.cpu.ram.set(address, value) {
+            return false;
+        }
+        self.invalidate(address);
+        true
+    }
+
+    pub fn input(&mut self, i: i64) -> Result<(), Error> {
+        self.cpu.input(i)?;
+        self.check_write();
+        Ok(())
+    }
+
+    pub fn execute(&mut self) -> Result<State, Error> {
+        loop {
+            let pc = self.cpu.pc;
+            let state = match self.compiled.ops.get(pc) {
+                Some(Some(op)) if self.valid[pc] => {
+                    self.cpu.last_write = None;
+                    let state = op(&mut self.cpu)?;
+                    self.cpu.instructions += 1;
+                    state
+                }
+                _ => self.cpu.step()?,
+            };
+            self.check_write();
+
+            if let Some(state) = state {
+                return Ok(state);
+            }
+        }
+    }
+
+    // Invalidates compiled code overwritten by the last instruction.
+    fn check_write(&mut self) {
+        if let Some((address, _)) = self.cpu.last_write.take() {
+            self.invalidate(address);
+        }
+    }
+
+    fn invalidate(&mut self, address: usize) {
+        if let Some(&Some(owner)) = self.compiled.owners.get(address) {
+            self.valid[owner] = false;
+        }
+    }
+}
+
+// Where an operand is read from, with position addresses already checked.
+#[derive(Copy, Clone)]
+enum Source {
+    Immediate(i64),
+    Position(usize),
+    Relative(i64),
+}
+
+impl Source {
+    fn new(mode: ParameterMode, value: i64) -> Option<Source> {
+        match mode {
+            ParameterMode::Immediate => Some(Source::Immediate(value)),
+            ParameterMode::Position if value >= 0 => Some(Source::Position(value as usize)),
+            ParameterMode::Position => None,
+            ParameterMode::Relative => Some(Source::Relative(value)),
+        }
+    }
+
+    fn read(self, cpu: &Computer) -> Result<i64, Error> {
+        match self {
+            Source::Immediate(x) => Ok(x),
+            Source::Position(p) => Ok(cpu.ram[p]),
+            Source::Relative(x) => Ok(cpu.ram[cpu.relative_address(&x)?]),
+        }
+    }
+
+    // Writes value to the operand's address.
+    fn write(self, cpu: &mut Computer, value: i64) -> Result<(), Error> {
+        let p = match self {
+            Source::Position(p) => p,
+            Source::Relative(x) => cpu.relative_address(&x)?,
+            Source::Immediate(_) => return Err(Error::ImmediateWrite { pc: cpu.pc }),
+        };
+
+        if !cpu.ram.set(p, value) {
+            return Err(Error::AddressOutOfRange {
+                pc: cpu.pc,
+                address: p,
+            });
+        }
+        cpu.last_write = Some((p, value));
+        Ok(())
+    }
+}
+
+// Compiles one instruction. Returns None for instructions that must be
+// interpreted: input, and anything that would fail at run time.
+fn compile(inst: &Instruction) -> Option<Op> {
+    let modes = inst.opcode.modes();
+    let sources = modes
+        .iter()
+        .zip(inst.cells[1..].iter())
+        .map(|(&m, &x)| Source::new(m, x))
+        .collect::<Option<Vec<_>>>()?;
+    let next = inst.address + inst.size();
+
+    let binary = |f: fn(i64, i64) -> Option<i64>| -> Option<Op> {
+        let (a, b, o) = (sources[0], sources[1], sources[2]);
+        if let Source::Immediate(_) = o {
+            return None;
+        }
+        Some(Box::new(move |cpu: &mut Computer| {
+            let value = f(a.read(cpu)?, b.read(cpu)?).ok_or(Error::Overflow { pc: cpu.pc })?;
+            o.write(cpu, value)?;
+            cpu.pc = next;
+            Ok(None)
+        }))
+    };
+
+    let jump = |when: bool| -> Option<Op> {
+        let (cond, target) = (sources[0], sources[1]);
+        Some(Box::new(move |cpu: &mut Computer| {
+            // The target is read even if the jump isn't taken, as the
+            // interpreter does, so a bad target fails either way.
+            let (cond, target) = (cond.read(cpu)?, target.read(cpu)?);
+            if (cond != 0) == when {
+                cpu.pc = cpu.address(&target)?;
+            } else {
+                cpu.pc = next;
+            }
+            Ok(None)
+        }))
+    };
+
+    match inst.opcode {
+        Opcode::Add(..) => binary(i64::checked_add),
+        Opcode::Mul(..) => binary(i64::checked_mul),
+        Opcode::LessThan(..) => binary(|a, b| Some((a < b) as i64)),
+        Opcode::Equals(..) => binary(|a, b| Some((a == b) as i64)),
+        Opcode::Input(..) | Opcode::Custom(..) => None,
+        Opcode::Output(..) => {
+            let a = sources[0];
+            Some(Box::new(move |cpu: &mut Computer| {
+                let out = a.read(cpu)?;
+                cpu.pc = next;
+                Ok(Some(State::Output(out)))
+            }))
+        }
+        Opcode::JumpIfTrue(..) => jump(true),
+        Opcode::JumpIfFalse(..) => jump(false),
+        Opcode::AdjustRelativeBase(..) => {
+            let a = sources[0];
+            Some(Box::new(move |cpu: &mut Computer| {
+                cpu.relative_base = cpu
+                    .relative_base
+                    .checked_add(a.read(cpu)?)
+                    .ok_or(Error::Overflow { pc: cpu.pc })?;
+                cpu.pc = next;
+                Ok(None)
+            }))
+        }
+        Opcode::Halt => Some(Box::new(|_: &mut Computer| Ok(Some(State::Halted)))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(compiled: &Rc<Compiled>, inputs: &[i64]) -> (Vec<i64>, CompiledComputer) {
+        let mut cpu = CompiledComputer::new(compiled.clone());
+        let mut inputs = inputs.iter();
+        let mut outputs = Vec::new();
+        loop {
+            match cpu.execute().unwrap() {
+                State::InputRequested => cpu.input(*inputs.next().unwrap()).unwrap(),
+                State::Output(x) => outputs.push(x),
+                State::Halted => return (outputs, cpu),
+            }
+        }
+    }
+
+    #[test]
+    fn test_compiled() {
+        // Reads n and outputs n, n-1, ..., 1 using a relative-base counter.
+        let program = vec![109, 14, 203, 0, 204, 0, 21201, 0, -1, 0, 1205, 0, 4, 99];
+        let compiled = Rc::new(Compiled::new(&program));
+        assert_eq!(compiled.len(), 5);
+
+        let (outputs, cpu) = run(&compiled, &[3]);
+        assert_eq!(outputs, vec![3, 2, 1]);
+        assert_eq!(cpu.instruction_count(), 12);
+    }
+
+    #[test]
+    fn test_self_modifying() {
+        // Outputs [12], then overwrites the operand of its out instruction
+        // and jumps back to it.
+        let program = vec![4, 12, 1101, 13, 0, 1, 1105, 1, 0, 0, 0, 0, 7, 99];
+        let compiled = Rc::new(Compiled::new(&program));
+        let mut cpu = CompiledComputer::new(compiled.clone());
+        assert_eq!(cpu.execute(), Ok(State::Output(7)));
+        assert_eq!(cpu.execute(), Ok(State::Output(99)));
+
+        let mut cpu = CompiledComputer::new(compiled);
+        assert!(cpu.poke(1, 13));
+        assert_eq!(cpu.execute(), Ok(State::Output(99)));
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let program = vec![
+            3, 31, 3, 32, 1002, 32, 10, 32, 1001, 31, -2, 31, 1007, 31, 0, 33, 1002, 33, 7, 33, 1,
+            33, 31, 31, 1, 32, 31, 31, 4, 31, 99, 0, 0, 0,
+        ];
+        let compiled = Rc::new(Compiled::new(&program));
+        for a in 0..5 {
+            for b in 0..5 {
+                let mut cpu = Computer::new(&program);
+                cpu.execute().unwrap();
+                cpu.input(a).unwrap();
+                cpu.execute().unwrap();
+                cpu.input(b).unwrap();
+                let expected = cpu.execute();
+
+                let mut fast = CompiledComputer::new(compiled.clone());
+                fast.execute().unwrap();
+                fast.input(a).unwrap();
+                fast.execute().unwrap();
+                fast.input(b).unwrap();
+                assert_eq!(fast.execute(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_untaken_jump_reads_target() {
+        // The jf isn't taken, but its target rel[-1] is still read and fails.
+        let program = vec![2106, 1, -1, 99];
+        let expected = Computer::new(&program).execute();
+        assert_eq!(expected, Err(Error::NegativeAddress { pc: 0, address: -1 }));
+
+        let mut cpu = CompiledComputer::new(Rc::new(Compiled::new(&program)));
+        assert_eq!(cpu.execute(), expected);
+    }
+}
@@ -0,0 +1,198 @@
+use super::disasm::{self, Line};
+use super::{Computer, Opcode, Word};
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+// Executed instructions and branches collected while coverage is enabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Coverage {
+    // Executions of the instruction at each address.
+    pub executed: HashMap<usize, u64>,
+    // Outcomes of each jt and jf that was executed.
+    pub branches: HashMap<usize, Branch>,
+}
+
+// How often a conditional jump was taken. A jump to the next instruction
+// counts as not taken.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl Coverage {
+    // Renders a disassembly of program with each line prefixed by how many
+    // times it was executed, gcov style: ##### marks code that never ran
+    // and - marks data. Conditional jumps also show their outcomes.
+    pub fn listing(&self, program: &[i64]) -> String {
+        let mut out = String::new();
+        for line in disasm::disassemble(program) {
+            let address = line.address();
+            let count = match (&line, self.executed.get(&address)) {
+                (_, Some(n)) => n.to_string(),
+                (Line::Code(_), None) => "#####".to_string(),
+                (Line::Data { .. }, None) => "-".to_string(),
+            };
+            write!(out, "{:>10} {}", count, line).unwrap();
+            if let Some(b) = self.branches.get(&address) {
+                write!(out, "  ; taken {}, not taken {}", b.taken, b.not_taken).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // Renders an LCOV tracefile for program under the source name name.
+    // Line numbers refer to lines of disasm::listing(program), so saving
+    // the listing as name lets LCOV tools annotate it.
+    pub fn lcov(&self, program: &[i64], name: &str) -> String {
+        let mut out = String::new();
+        writeln!(out, "TN:").unwrap();
+        writeln!(out, "SF:{}", name).unwrap();
+
+        let (mut found, mut hit) = (0, 0);
+        let mut branches = Vec::new();
+        for (i, line) in disasm::disassemble(program).iter().enumerate() {
+            let inst = match line {
+                Line::Code(inst) => inst,
+                Line::Data { .. } => continue,
+            };
+            let number = i + 1;
+            let count = self.executed.get(&inst.address).cloned().unwrap_or(0);
+            writeln!(out, "DA:{},{}", number, count).unwrap();
+            found += 1;
+            if count > 0 {
+                hit += 1;
+            }
+
+            if let Opcode::JumpIfTrue(..) | Opcode::JumpIfFalse(..) = inst.opcode {
+                let b = self.branches.get(&inst.address);
+                branches.push((number, b.map(|b| b.taken)));
+                branches.push((number, b.map(|b| b.not_taken)));
+            }
+        }
+        writeln!(out, "LF:{}", found).unwrap();
+        writeln!(out, "LH:{}", hit).unwrap();
+
+        for (i, &(number, taken)) in branches.iter().enumerate() {
+            let taken = taken.map_or("-".to_string(), |x| x.to_string());
+            writeln!(out, "BRDA:{},0,{},{}", number, i % 2, taken).unwrap();
+        }
+        let branches_hit = branches.iter().filter(|(_, x)| x.unwrap_or(0) > 0);
+        writeln!(out, "BRF:{}", branches.len()).unwrap();
+        writeln!(out, "BRH:{}", branches_hit.count()).unwrap();
+
+        writeln!(out, "end_of_record").unwrap();
+        out
+    }
+}
+
+impl<W: Word> Computer<W> {
+    // Starts recording coverage. Clears any existing coverage.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::default());
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // Stops recording coverage and returns what was collected.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    // Records an instruction that just executed from pc.
+    pub(super) fn cover(&mut self, pc: usize, opcode: Opcode) {
+        let new_pc = self.pc;
+        if let Some(coverage) = self.coverage.as_mut() {
+            *coverage.executed.entry(pc).or_default() += 1;
+
+            if let Opcode::JumpIfTrue(..) | Opcode::JumpIfFalse(..) = opcode {
+                let branch = coverage.branches.entry(pc).or_default();
+                if new_pc == pc + opcode.size() {
+                    branch.not_taken += 1;
+                } else {
+                    branch.taken += 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::State;
+
+    // Reads a number, then counts it down to 0, outputting each value. The
+    // second jt is never taken, so the code it jumps to never runs.
+    const PROGRAM: [i64; 19] = [
+        3, 18, 4, 18, 1001, 18, -1, 18, 1005, 18, 2, 1005, 18, 15, 99, 104, 1, 99, 0,
+    ];
+
+    fn run(input: i64) -> Coverage {
+        let mut cpu = Computer::new(&PROGRAM);
+        cpu.enable_coverage();
+        cpu.execute().unwrap();
+        cpu.input(input).unwrap();
+        while cpu.execute().unwrap() != State::Halted {}
+        cpu.take_coverage().unwrap()
+    }
+
+    #[test]
+    fn test_coverage() {
+        let coverage = run(3);
+        assert_eq!(coverage.executed[&0], 1);
+        assert_eq!(coverage.executed[&2], 3);
+        assert_eq!(coverage.executed.get(&15), None);
+        assert_eq!(
+            coverage.branches[&8],
+            Branch {
+                taken: 2,
+                not_taken: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_listing() {
+        let expected = "         1      0: 3 18                             in [18]
+         3      2: 4 18                             out [18]
+         3      4: 1001 18 -1 18                    add [18], #-1, [18]
+         3      8: 1005 18 2                        jt [18], #2  ; taken 2, not taken 1
+         1     11: 1005 18 15                       jt [18], #15  ; taken 0, not taken 1
+         1     14: 99                               hlt
+     #####     15: 104 1                            out #1
+     #####     17: 99                               hlt
+         -     18: 0                                db 0
+";
+        assert_eq!(run(3).listing(&PROGRAM), expected);
+    }
+
+    #[test]
+    fn test_lcov() {
+        let expected = "TN:
+SF:countdown.txt
+DA:1,1
+DA:2,1
+DA:3,1
+DA:4,1
+DA:5,1
+DA:6,1
+DA:7,0
+DA:8,0
+LF:8
+LH:6
+BRDA:4,0,0,0
+BRDA:4,0,1,1
+BRDA:5,0,0,0
+BRDA:5,0,1,1
+BRF:4
+BRH:2
+end_of_record
+";
+        assert_eq!(run(1).lcov(&PROGRAM, "countdown.txt"), expected);
+    }
+}
@@ -0,0 +1,246 @@
+use super::{parse_opcode, Computer, Error, Opcode, ParameterMode, Word};
+
+use std::error;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+// An instruction added to a Computer with register_opcode. Like the built-in
+// ones, each operand's mode comes from the digits of the opcode, and an
+// output operand must not be in immediate mode. Only the interpreter knows
+// about custom opcodes: disassembly and compilation treat them as data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CustomOpcode {
+    // The opcode modulo 100. Must not be a built-in opcode.
+    pub number: i64,
+    pub mnemonic: &'static str,
+    // Number of operands, at most 3 since an opcode has three mode digits.
+    pub arity: usize,
+    // Index of the operand the handler's result is written to, if any.
+    pub output: Option<usize>,
+}
+
+// Why register_opcode rejected an opcode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RegisterError {
+    // The number is a built-in opcode or doesn't fit in two digits.
+    Reserved { number: i64 },
+    // More operands than an opcode has mode digits for.
+    TooManyOperands { number: i64, arity: usize },
+    // The output operand is past the last operand.
+    BadOutput { number: i64, output: usize },
+}
+
+impl fmt::Display for RegisterError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegisterError::Reserved { number } => {
+                write!(f, "opcode {} is built in or out of range", number)
+            }
+            RegisterError::TooManyOperands { number, arity } => {
+                write!(
+                    f,
+                    "opcode {} has {} operands, at most 3 allowed",
+                    number, arity
+                )
+            }
+            RegisterError::BadOutput { number, output } => {
+                write!(f, "opcode {} writes to missing operand {}", number, output)
+            }
+        }
+    }
+}
+
+impl error::Error for RegisterError {}
+
+// Runs a custom instruction. Called with the values of the operands it
+// reads, in order, and returns the value to write to its output operand. A
+// result of None writes nothing.
+pub(super) type Handler<W> = Arc<Mutex<dyn FnMut(&[W]) -> Option<W> + Send>>;
+
+impl<W: Word> Computer<W> {
+    // Adds a custom instruction, replacing any earlier registration of the
+    // same number. Clones of this Computer share the handler. Fails if
+    // opcode clashes with a built-in opcode or its operands don't fit in
+    // three mode digits.
+    pub fn register_opcode<F>(
+        &mut self,
+        opcode: CustomOpcode,
+        handler: F,
+    ) -> Result<(), RegisterError>
+    where
+        F: FnMut(&[W]) -> Option<W> + Send + 'static,
+    {
+        let number = opcode.number;
+        if !(0..100).contains(&number) || parse_opcode(0, number).is_ok() {
+            return Err(RegisterError::Reserved { number });
+        }
+        if opcode.arity > 3 {
+            let arity = opcode.arity;
+            return Err(RegisterError::TooManyOperands { number, arity });
+        }
+        if let Some(output) = opcode.output.filter(|&o| o >= opcode.arity) {
+            return Err(RegisterError::BadOutput { number, output });
+        }
+
+        self.custom.retain(|(c, _)| c.number != opcode.number);
+        self.custom.push((opcode, Arc::new(Mutex::new(handler))));
+        // A replaced registration may still be in the decode cache.
+        if let Some(cache) = self.decode_cache.as_mut() {
+            cache.clear();
+        }
+        Ok(())
+    }
+
+    // Executes the custom instruction at pc.
+    pub(super) fn execute_custom(&mut self, opcode: Opcode) -> Result<(), Error> {
+        let (custom, modes) = match opcode {
+            Opcode::Custom(custom, modes) => (custom, modes),
+            _ => unreachable!(),
+        };
+        let handler = match self.custom.iter().find(|(c, _)| *c == custom) {
+            Some((_, handler)) => handler.clone(),
+            None => unreachable!("decoded opcodes are registered"),
+        };
+
+        let operands = self.read_operands(opcode)?;
+        let result = (handler.lock().unwrap())(&operands);
+        if let (Some(o), Some(value)) = (custom.output, result) {
+            self.instruction_output(modes[o], o + 1, value)?;
+        }
+        self.pc += opcode.size();
+        Ok(())
+    }
+}
+
+// Decodes the opcode raw found at pc, falling back to the registered custom
+// opcodes.
+pub(super) fn parse<W>(
+    custom: &[(CustomOpcode, Handler<W>)],
+    pc: usize,
+    raw: i64,
+) -> Result<Opcode, Error> {
+    let err = match parse_opcode(pc, raw) {
+        Err(err @ Error::BadOpcode { .. }) => err,
+        result => return result,
+    };
+    let custom = match custom.iter().find(|(c, _)| c.number == raw % 100) {
+        Some(&(c, _)) => c,
+        None => return Err(err),
+    };
+
+    // parse_opcode has already checked the mode digits.
+    let mode = |digit: i64| ParameterMode::new(pc, raw / digit % 10);
+    Ok(Opcode::Custom(
+        custom,
+        [mode(100)?, mode(1000)?, mode(10000)?],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::trace::Event;
+    use crate::intcode::State;
+
+    const RAND: CustomOpcode = CustomOpcode {
+        number: 42,
+        mnemonic: "rand",
+        arity: 1,
+        output: Some(0),
+    };
+
+    const PRINT: CustomOpcode = CustomOpcode {
+        number: 43,
+        mnemonic: "dbg",
+        arity: 2,
+        output: None,
+    };
+
+    #[test]
+    fn test_custom_opcode() {
+        // rand [9]; rand rel[1]; out [9]; out [1]; hlt
+        let program = [42, 9, 242, 1, 4, 9, 4, 1, 99, 0];
+        let mut cpu = Computer::new(&program);
+        assert_eq!(cpu.execute(), Err(Error::BadOpcode { pc: 0, opcode: 42 }));
+
+        let mut next = 0;
+        cpu.register_opcode(RAND, move |_| {
+            next += 7;
+            Some(next)
+        })
+        .unwrap();
+        assert_eq!(cpu.execute(), Ok(State::Output(7)));
+        assert_eq!(cpu.execute(), Ok(State::Output(14)));
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+        assert_eq!(cpu.instruction_count(), 5);
+    }
+
+    #[test]
+    fn test_operands() {
+        // dbg #5, [6]; dbg rel[-1], #2; hlt
+        let program = [143, 5, 6, 1243, -1, 2, 99];
+        let mut cpu = Computer::new(&program);
+        let (tx, rx) = std::sync::mpsc::channel();
+        cpu.register_opcode(PRINT, move |args: &[i64]| {
+            tx.send(args.to_vec()).unwrap();
+            None
+        })
+        .unwrap();
+        assert_eq!(
+            cpu.execute(),
+            Err(Error::NegativeAddress { pc: 3, address: -1 })
+        );
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![vec![5, 99]]);
+    }
+
+    #[test]
+    fn test_immediate_write() {
+        let mut cpu = Computer::new(&[142, 0, 99]);
+        cpu.register_opcode(RAND, |_| Some(1)).unwrap();
+        assert_eq!(cpu.execute(), Err(Error::ImmediateWrite { pc: 0 }));
+    }
+
+    #[test]
+    fn test_traced() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut cpu = Computer::new(&[43, 0, 0, 99]);
+        cpu.register_opcode(PRINT, |_| None).unwrap();
+        let tracer_seen = seen.clone();
+        cpu.set_tracer(Box::new(move |event: &Event| {
+            tracer_seen.lock().unwrap().push(event.opcode.mnemonic());
+        }));
+        // The clone has no tracer but shares the handler.
+        assert_eq!(cpu.clone().execute(), Ok(State::Halted));
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+        assert_eq!(*seen.lock().unwrap(), vec!["dbg", "hlt"]);
+    }
+
+    #[test]
+    fn test_register_errors() {
+        let mut cpu = Computer::new(&[99]);
+        let mut err = |opcode| cpu.register_opcode(opcode, |_| None).unwrap_err();
+        assert_eq!(
+            err(CustomOpcode { number: 9, ..RAND }),
+            RegisterError::Reserved { number: 9 }
+        );
+        assert_eq!(
+            err(CustomOpcode {
+                number: 142,
+                ..RAND
+            }),
+            RegisterError::Reserved { number: 142 }
+        );
+        assert_eq!(
+            err(CustomOpcode { arity: 4, ..RAND }),
+            RegisterError::TooManyOperands {
+                number: 42,
+                arity: 4
+            }
+        );
+        let output = Some(2);
+        assert_eq!(
+            err(CustomOpcode { output, ..PRINT }).to_string(),
+            "opcode 43 writes to missing operand 2"
+        );
+    }
+}
@@ -0,0 +1,420 @@
+use super::disasm::Instruction;
+use super::{Computer, Error, State};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::io::{BufRead, Write};
+
+// Number of instructions the debugger can step back over.
+const HISTORY: usize = 100_000;
+
+// Most cells the x command prints at once.
+const EXAMINE_LIMIT: i64 = 1000;
+
+// Wraps a Computer with breakpoints on pc and watchpoints on memory.
+pub struct Debugger {
+    pub cpu: Computer,
+    breakpoints: BTreeSet<usize>,
+    // Watched addresses and the last value seen at each.
+    watchpoints: BTreeMap<usize, i64>,
+}
+
+// Why the debugger stopped.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint { address: usize, old: i64, new: i64 },
+    InputRequested,
+    Output(i64),
+    Halted,
+}
+
+impl Debugger {
+    pub fn new(program: &[i64]) -> Debugger {
+        let mut cpu = Computer::new(program);
+        cpu.enable_history(HISTORY);
+        Debugger {
+            cpu,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn add_watchpoint(&mut self, address: usize) {
+        self.watchpoints.insert(address, self.cpu.ram[address]);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address).is_some()
+    }
+
+    // Decodes the instruction at pc.
+    pub fn current_instruction(&self) -> Result<Instruction, Error> {
+        let pc = self.cpu.pc();
+        Instruction::decode(pc, &self.cpu.ram.range(pc..pc + 4))
+    }
+
+    // Executes one instruction. Input instructions are not executed; use
+    // input() to provide a value.
+    pub fn step(&mut self) -> Result<Stop, Error> {
+        let stop = match self.cpu.step()? {
+            Some(State::InputRequested) => return Ok(Stop::InputRequested),
+            Some(State::Halted) => return Ok(Stop::Halted),
+            Some(State::Output(x)) => Stop::Output(x),
+            None => Stop::Stepped,
+        };
+
+        Ok(self.check_watchpoints().unwrap_or(stop))
+    }
+
+    // Executes instructions until a breakpoint, watchpoint, input, output or
+    // halt. Always executes at least one instruction so resuming from a
+    // breakpoint makes progress.
+    pub fn resume(&mut self) -> Result<Stop, Error> {
+        loop {
+            let stop = self.step()?;
+            if stop != Stop::Stepped {
+                return Ok(stop);
+            }
+            if self.breakpoints.contains(&self.cpu.pc()) {
+                return Ok(Stop::Breakpoint(self.cpu.pc()));
+            }
+        }
+    }
+
+    pub fn input(&mut self, value: i64) -> Result<Stop, Error> {
+        self.cpu.input(value)?;
+        Ok(self.check_watchpoints().unwrap_or(Stop::Stepped))
+    }
+
+    // Undoes up to n instructions. Returns the number undone.
+    pub fn step_back(&mut self, n: usize) -> usize {
+        let count = self.cpu.step_back(n);
+        self.check_watchpoints();
+        count
+    }
+
+    // Undoes instructions until just before the last input or output.
+    pub fn rewind_to_io(&mut self) -> usize {
+        let count = self.cpu.rewind_to_io();
+        self.check_watchpoints();
+        count
+    }
+
+    // Sets ram[address] without triggering watchpoints. Pokes are not
+    // recorded in history, so stepping back does not undo them.
+    pub fn poke(&mut self, address: usize, value: i64) -> bool {
+        if !self.cpu.ram.set(address, value) {
+            return false;
+        }
+        if let Some(x) = self.watchpoints.get_mut(&address) {
+            *x = value;
+        }
+        true
+    }
+
+    fn check_watchpoints(&mut self) -> Option<Stop> {
+        let ram = &self.cpu.ram;
+        let mut ret = None;
+        for (&address, last) in self.watchpoints.iter_mut() {
+            let new = ram[address];
+            if new != *last && ret.is_none() {
+                ret = Some(Stop::Watchpoint {
+                    address,
+                    old: *last,
+                    new,
+                });
+            }
+            *last = new;
+        }
+        ret
+    }
+
+    // Runs an interactive session, reading commands from input until quit or
+    // end of input.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, out: &mut W) -> io::Result<()> {
+        let mut lines = input.lines();
+        loop {
+            write!(out, "(idb) ")?;
+            out.flush()?;
+            let line = match lines.next() {
+                Some(x) => x?,
+                None => {
+                    writeln!(out)?;
+                    return Ok(());
+                }
+            };
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let args: Result<Vec<i64>, _> = words[1..].iter().map(|x| x.parse()).collect();
+            let args = match args {
+                Ok(x) => x,
+                Err(_) => {
+                    writeln!(out, "bad number")?;
+                    continue;
+                }
+            };
+            let arg = |i: usize| args.get(i).cloned();
+            let address = |i: usize| arg(i).filter(|&x| x >= 0).map(|x| x as usize);
+
+            match (words[0], args.len()) {
+                ("s", _) | ("step", _) => {
+                    let n = arg(0).unwrap_or(1).max(1);
+                    for i in 0..n {
+                        let stop = self.step();
+                        if i + 1 == n || stop != Ok(Stop::Stepped) {
+                            self.report(stop, out)?;
+                            break;
+                        }
+                    }
+                }
+                ("back", _) => {
+                    let n = self.step_back(arg(0).unwrap_or(1).max(1) as usize);
+                    writeln!(out, "stepped back {}", n)?;
+                    self.print_location(out)?;
+                }
+                ("rewind", 0) => {
+                    let n = self.rewind_to_io();
+                    writeln!(out, "stepped back {}", n)?;
+                    self.print_location(out)?;
+                }
+                ("c", 0) | ("continue", 0) => loop {
+                    match self.resume() {
+                        Ok(Stop::Output(x)) => writeln!(out, "output: {}", x)?,
+                        stop => {
+                            self.report(stop, out)?;
+                            break;
+                        }
+                    }
+                },
+                ("b", 1) | ("break", 1) if address(0).is_some() => {
+                    self.add_breakpoint(address(0).unwrap());
+                }
+                ("d", 1) | ("delete", 1) if address(0).is_some() => {
+                    if !self.remove_breakpoint(address(0).unwrap()) {
+                        writeln!(out, "no breakpoint at {}", args[0])?;
+                    }
+                }
+                ("w", 1) | ("watch", 1) if address(0).is_some() => {
+                    self.add_watchpoint(address(0).unwrap());
+                }
+                ("unwatch", 1) if address(0).is_some() => {
+                    if !self.remove_watchpoint(address(0).unwrap()) {
+                        writeln!(out, "no watchpoint at {}", args[0])?;
+                    }
+                }
+                ("i", 0) | ("info", 0) => {
+                    self.print_location(out)?;
+                    writeln!(out, "breakpoints: {}", join(self.breakpoints.iter()))?;
+                    writeln!(out, "watchpoints: {}", join(self.watchpoints.keys()))?;
+                }
+                ("x", 1) | ("x", 2) if address(0).is_some() => {
+                    let start = address(0).unwrap();
+                    let n = arg(1).unwrap_or(1).clamp(1, EXAMINE_LIMIT) as usize;
+                    for address in (0..n).map_while(|i| start.checked_add(i)) {
+                        writeln!(out, "{:>6}: {}", address, self.cpu.ram.get(address))?;
+                    }
+                }
+                ("poke", 2) if address(0).is_some() => {
+                    if !self.poke(address(0).unwrap(), args[1]) {
+                        writeln!(out, "address beyond memory limit")?;
+                    }
+                }
+                ("input", 1) => {
+                    let stop = self.input(args[0]);
+                    self.report(stop, out)?;
+                }
+                ("l", _) | ("list", _) => self.print_listing(arg(0).unwrap_or(5), out)?,
+                ("q", 0) | ("quit", 0) => return Ok(()),
+                ("h", 0) | ("help", 0) => writeln!(out, "{}", HELP)?,
+                _ => writeln!(out, "unknown command (try help)")?,
+            };
+        }
+    }
+
+    fn report<W: Write>(&self, stop: Result<Stop, Error>, out: &mut W) -> io::Result<()> {
+        match stop {
+            Ok(Stop::Stepped) => (),
+            Ok(Stop::Breakpoint(pc)) => writeln!(out, "breakpoint at {}", pc)?,
+            Ok(Stop::Watchpoint { address, old, new }) => {
+                writeln!(out, "watchpoint: ram[{}] {} -> {}", address, old, new)?
+            }
+            Ok(Stop::InputRequested) => writeln!(out, "input requested (use input <value>)")?,
+            Ok(Stop::Output(x)) => writeln!(out, "output: {}", x)?,
+            Ok(Stop::Halted) => writeln!(out, "halted")?,
+            Err(e) => writeln!(out, "error: {}", e)?,
+        };
+        self.print_location(out)
+    }
+
+    fn print_location<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let inst = match self.current_instruction() {
+            Ok(inst) => inst.to_string(),
+            Err(e) => format!("<{}>", e),
+        };
+        writeln!(
+            out,
+            "pc {} rb {}: {}",
+            self.cpu.pc(),
+            self.cpu.relative_base(),
+            inst
+        )
+    }
+
+    // Prints up to n instructions starting at pc.
+    fn print_listing<W: Write>(&self, n: i64, out: &mut W) -> io::Result<()> {
+        let mut pc = self.cpu.pc();
+        for _ in 0..n {
+            match Instruction::decode(pc, &self.cpu.ram.range(pc..pc + 4)) {
+                Ok(inst) => {
+                    writeln!(out, "{:>6}: {}", pc, inst)?;
+                    pc += inst.size();
+                }
+                Err(_) => {
+                    writeln!(out, "{:>6}: db {}", pc, self.cpu.ram[pc])?;
+                    pc += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn join<'a, I: Iterator<Item = &'a usize>>(xs: I) -> String {
+    let xs: Vec<_> = xs.map(|x| x.to_string()).collect();
+    if xs.is_empty() {
+        "none".to_string()
+    } else {
+        xs.join(" ")
+    }
+}
+
+const HELP: &str = "commands:
+    s, step [n]          execute n instructions (default 1)
+    back [n]             undo n instructions (default 1)
+    rewind               undo back to just before the last input or output
+    c, continue          run until a breakpoint, watchpoint, input or halt
+    b, break <pc>        set a breakpoint
+    d, delete <pc>       remove a breakpoint
+    w, watch <addr>      stop when ram[addr] changes
+    unwatch <addr>       remove a watchpoint
+    i, info              print pc, relative base, breakpoints and watchpoints
+    x <addr> [n]         print n cells of ram starting at addr (at most 1000)
+    poke <addr> <value>  set ram[addr]
+    input <value>        provide input to a waiting input instruction
+    l, list [n]          disassemble n instructions from pc
+    q, quit              exit the debugger";
+
+// Runs an interactive debugger session on stdin and stdout.
+pub fn debug_console_program(program: &[i64]) -> io::Result<()> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    Debugger::new(program).run(stdin.lock(), &mut stdout.lock())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reads a number, adds 1 to it until it is at least 3, and outputs it.
+    const PROGRAM: [i64; 18] = [
+        3, 16, 1001, 16, 1, 16, 1007, 16, 3, 17, 1005, 17, 2, 4, 16, 99, 0, 0,
+    ];
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let mut dbg = Debugger::new(&PROGRAM);
+        assert_eq!(dbg.resume(), Ok(Stop::InputRequested));
+        assert_eq!(dbg.input(0), Ok(Stop::Stepped));
+
+        dbg.add_breakpoint(10);
+        assert_eq!(dbg.resume(), Ok(Stop::Breakpoint(10)));
+        assert_eq!(dbg.cpu.ram[16], 1);
+
+        dbg.add_watchpoint(16);
+        assert_eq!(
+            dbg.resume(),
+            Ok(Stop::Watchpoint {
+                address: 16,
+                old: 1,
+                new: 2
+            })
+        );
+        assert_eq!(dbg.cpu.pc(), 6);
+
+        assert!(dbg.remove_watchpoint(16));
+        assert!(dbg.remove_breakpoint(10));
+        assert!(dbg.poke(16, 41));
+        assert_eq!(dbg.step(), Ok(Stop::Stepped));
+        assert_eq!(dbg.resume(), Ok(Stop::Output(41)));
+        assert_eq!(dbg.resume(), Ok(Stop::Halted));
+
+        assert_eq!(dbg.rewind_to_io(), 2);
+        assert_eq!(dbg.cpu.pc(), 13);
+        dbg.add_watchpoint(16);
+        assert_eq!(dbg.step_back(3), 3);
+        assert_eq!(dbg.cpu.pc(), 2);
+        assert_eq!(
+            dbg.step(),
+            Ok(Stop::Watchpoint {
+                address: 16,
+                old: 1,
+                new: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_run() {
+        let script = "break 10\nc\ninput 1\nc\ninfo\nx 16 2\npoke 16 5\ns 3\nc\n";
+        let mut out = Vec::new();
+        Debugger::new(&PROGRAM)
+            .run(script.as_bytes(), &mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        let expected = "(idb) (idb) input requested (use input <value>)
+pc 0 rb 0: in [16]
+(idb) pc 2 rb 0: add [16], #1, [16]
+(idb) breakpoint at 10
+pc 10 rb 0: jt [17], #2
+(idb) pc 10 rb 0: jt [17], #2
+breakpoints: 10
+watchpoints: none
+(idb)     16: 2
+    17: 1
+(idb) (idb) pc 10 rb 0: jt [17], #2
+(idb) output: 6
+halted
+pc 15 rb 0: hlt
+(idb) \n";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_examine_limits() {
+        let script = "x 0 9223372036854775807\nx 9223372036854775806 2\n";
+        let mut out = Vec::new();
+        Debugger::new(&PROGRAM)
+            .run(script.as_bytes(), &mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 1000 + 2 + 1);
+        assert_eq!(lines[999], "   999: 0");
+        assert_eq!(lines[1000], "(idb) 9223372036854775806: 0");
+        assert_eq!(lines[1001], "9223372036854775807: 0");
+    }
+}
@@ -0,0 +1,692 @@
+use super::cfg::{self, name, Block, Cfg, Exit, Function};
+use super::disasm::{Instruction, Operand};
+use super::Opcode;
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+// Decompiles program into C-like pseudocode: main for the code at address
+// 0, and a function fN for each call target N.
+//
+// Memory is written mem[x]. Inside functions, stack cells are named by
+// where they sit relative to the caller's relative base: arg0, arg1, ...
+// are the cells between the return address and the callee's base, where
+// callers put arguments, and out0, out1, ... are the cells just past the
+// callee's own return address slot, where it puts arguments for its calls
+// and reads back their results. Where the relative base can't be tracked,
+// cells are written rel[x] and base adjustments show up as rel += x.
+pub fn decompile(program: &[i64]) -> String {
+    let cfg = Cfg::new(program);
+    let flags = flag_cells(&cfg);
+
+    let mut out = String::new();
+    for (i, function) in cfg.functions.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let writer = FunctionWriter::new(&cfg, function, &flags);
+        let body = writer.region(0, writer.blocks.len(), None, None);
+        let mut labels = HashSet::new();
+        goto_targets(&body, &mut labels);
+
+        writeln!(out, "void {}() {{", name(function.entry)).unwrap();
+        write_stmts(&mut out, &body, 1, &labels);
+        writeln!(out, "}}").unwrap();
+    }
+    out
+}
+
+#[derive(Clone)]
+enum Stmt {
+    Line(String),
+    Label(usize),
+    Goto(usize),
+    Break,
+    If {
+        cond: String,
+        then: Vec<Stmt>,
+        otherwise: Vec<Stmt>,
+    },
+    While {
+        cond: String,
+        body: Vec<Stmt>,
+    },
+    DoWhile {
+        body: Vec<Stmt>,
+        cond: String,
+    },
+}
+
+// A branch condition, written both ways round.
+#[derive(Clone)]
+struct Cond {
+    taken: String,
+    not_taken: String,
+}
+
+impl Cond {
+    fn new(truthy: String, falsy: String, if_true: bool) -> Cond {
+        if if_true {
+            Cond {
+                taken: truthy,
+                not_taken: falsy,
+            }
+        } else {
+            Cond {
+                taken: falsy,
+                not_taken: truthy,
+            }
+        }
+    }
+}
+
+// A block's statements, without the jump that ends it.
+struct BlockCode {
+    stmts: Vec<Stmt>,
+    cond: Option<Cond>,
+    // Address an indirect jump or call goes to.
+    target: Option<String>,
+}
+
+struct FunctionWriter<'a> {
+    // The function's blocks in address order, and the code for each.
+    blocks: Vec<&'a Block>,
+    code: Vec<BlockCode>,
+}
+
+impl<'a> FunctionWriter<'a> {
+    fn new(cfg: &'a Cfg, function: &Function, flags: &HashSet<i64>) -> FunctionWriter<'a> {
+        let bases = bases(cfg, function);
+        let names = Names {
+            frame: if function.entry == 0 {
+                None
+            } else {
+                Some(function.frame)
+            },
+        };
+
+        let blocks: Vec<&Block> = function.blocks.iter().map(|x| &cfg.blocks[x]).collect();
+        let code = blocks
+            .iter()
+            .map(|block| names.block_code(block, bases[&block.start], flags))
+            .collect();
+        FunctionWriter { blocks, code }
+    }
+
+    // Structures blocks[lo..hi]. follow is where control goes after the
+    // last of them, and header is the index of a loop already opened at lo.
+    fn region(
+        &self,
+        lo: usize,
+        hi: usize,
+        follow: Option<usize>,
+        header: Option<usize>,
+    ) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        let mut i = lo;
+        while i < hi {
+            if header != Some(i) {
+                out.push(Stmt::Label(self.blocks[i].start));
+                if let Some(j) = self.back_edge(i, hi) {
+                    let after = self.start(j + 1, hi, follow);
+                    out.push(self.looped(i, j, after));
+                    i = j + 1;
+                    continue;
+                }
+            }
+
+            if let Some((stmts, resume)) = self.conditional(i, hi, follow) {
+                out.extend(stmts);
+                i = resume;
+                continue;
+            }
+
+            out.extend(self.code[i].stmts.iter().cloned());
+            out.extend(self.exit(i, self.start(i + 1, hi, follow)));
+            i += 1;
+        }
+        out
+    }
+
+    // Address of blocks[i] if it is in the region ending at hi, otherwise
+    // follow.
+    fn start(&self, i: usize, hi: usize, follow: Option<usize>) -> Option<usize> {
+        if i < hi {
+            Some(self.blocks[i].start)
+        } else {
+            follow
+        }
+    }
+
+    // Index of the block at address in lo..hi, or hi if address is follow.
+    fn find(&self, address: usize, lo: usize, hi: usize, follow: Option<usize>) -> Option<usize> {
+        (lo..hi)
+            .find(|&k| self.blocks[k].start == address)
+            .or_else(|| Some(hi).filter(|_| follow == Some(address)))
+    }
+
+    // The last block in i..hi that jumps back to blocks[i].
+    fn back_edge(&self, i: usize, hi: usize) -> Option<usize> {
+        let header = self.blocks[i].start;
+        (i..hi).rev().find(|&j| match self.blocks[j].exit {
+            Exit::Jump(target) | Exit::Branch { taken: target, .. } => target == header,
+            _ => false,
+        })
+    }
+
+    // A loop over blocks i..=j, where j jumps back to i and after is where
+    // control goes once it is done.
+    fn looped(&self, i: usize, j: usize, after: Option<usize>) -> Stmt {
+        let header = self.blocks[i].start;
+        match (&self.blocks[i].exit, &self.blocks[j].exit) {
+            (_, Exit::Branch { fall, .. }) if Some(*fall) == after => {
+                let mut body = self.region(i, j, Some(self.blocks[j].start), Some(i));
+                if j > i {
+                    body.push(Stmt::Label(self.blocks[j].start));
+                }
+                body.extend(self.code[j].stmts.iter().cloned());
+                Stmt::DoWhile {
+                    body,
+                    cond: self.code[j].cond.clone().unwrap().taken,
+                }
+            }
+            (Exit::Branch { taken, fall, .. }, Exit::Jump(_))
+                if j > i && Some(*taken) == after && *fall == self.blocks[i + 1].start =>
+            {
+                let cond = self.code[i].cond.clone().unwrap();
+                let body = self.region(i + 1, j + 1, Some(header), None);
+                if self.code[i].stmts.is_empty() {
+                    return Stmt::While {
+                        cond: cond.not_taken,
+                        body,
+                    };
+                }
+
+                let mut stmts = self.code[i].stmts.to_vec();
+                stmts.push(Stmt::If {
+                    cond: cond.taken,
+                    then: vec![Stmt::Break],
+                    otherwise: Vec::new(),
+                });
+                stmts.extend(body);
+                Stmt::While {
+                    cond: "1".to_string(),
+                    body: stmts,
+                }
+            }
+            _ => Stmt::While {
+                cond: "1".to_string(),
+                body: self.region(i, j + 1, Some(header), Some(i)),
+            },
+        }
+    }
+
+    // An if or if-else starting with the branch that ends blocks[i], along
+    // with the index to carry on from.
+    fn conditional(
+        &self,
+        i: usize,
+        hi: usize,
+        follow: Option<usize>,
+    ) -> Option<(Vec<Stmt>, usize)> {
+        let (taken, fall) = match self.blocks[i].exit {
+            Exit::Branch { taken, fall, .. } => (taken, fall),
+            _ => return None,
+        };
+        if i + 1 >= hi || fall != self.blocks[i + 1].start {
+            return None;
+        }
+        let k = self.find(taken, i + 2, hi, follow)?;
+
+        let cond = self.code[i].cond.clone().unwrap().not_taken;
+        let mut out = self.code[i].stmts.to_vec();
+
+        // The then part of an if-else ends by jumping over the else part.
+        if let Exit::Jump(join) = self.blocks[k - 1].exit {
+            if let Some(m) = self.find(join, k + 1, hi, follow) {
+                out.push(Stmt::If {
+                    cond,
+                    then: self.region(i + 1, k, Some(join), None),
+                    otherwise: self.region(k, m, Some(join), None),
+                });
+                return Some((out, m));
+            }
+        }
+
+        out.push(Stmt::If {
+            cond,
+            then: self.region(i + 1, k, Some(taken), None),
+            otherwise: Vec::new(),
+        });
+        Some((out, k))
+    }
+
+    // The way out of blocks[i] when control would otherwise go on to next.
+    fn exit(&self, i: usize, next: Option<usize>) -> Vec<Stmt> {
+        let code = &self.code[i];
+        let goto = |target: usize| {
+            if next == Some(target) {
+                vec![]
+            } else {
+                vec![Stmt::Goto(target)]
+            }
+        };
+
+        match self.blocks[i].exit {
+            Exit::Fall(target) | Exit::Jump(target) => goto(target),
+            Exit::Branch { taken, fall, .. } => {
+                let mut out = vec![Stmt::If {
+                    cond: code.cond.clone().unwrap().taken,
+                    then: vec![Stmt::Goto(taken)],
+                    otherwise: Vec::new(),
+                }];
+                out.extend(goto(fall));
+                out
+            }
+            Exit::Call { target, ret } => {
+                let mut out = vec![Stmt::Line(format!("{}();", name(target)))];
+                out.extend(goto(ret));
+                out
+            }
+            Exit::IndirectCall { ret, .. } => {
+                let target = code.target.as_ref().unwrap();
+                let mut out = vec![Stmt::Line(format!("(*{})();", target))];
+                out.extend(goto(ret));
+                out
+            }
+            Exit::Return => vec![Stmt::Line("return;".to_string())],
+            Exit::Halt => vec![Stmt::Line("halt();".to_string())],
+            Exit::Indirect { fall, .. } => {
+                let jump = Stmt::Line(format!("goto *{};", code.target.as_ref().unwrap()));
+                let mut out = match &code.cond {
+                    Some(cond) => vec![Stmt::If {
+                        cond: cond.taken.clone(),
+                        then: vec![jump],
+                        otherwise: Vec::new(),
+                    }],
+                    None => vec![jump],
+                };
+                out.extend(fall.into_iter().flat_map(goto));
+                out
+            }
+        }
+    }
+}
+
+// Names cells within one function.
+struct Names {
+    // Stack frame size, or None in main, where the relative base starts at
+    // 0 and relative cells can be named by address.
+    frame: Option<i64>,
+}
+
+impl Names {
+    fn operand(&self, op: &Operand, base: Option<i64>) -> String {
+        match (*op, base) {
+            (Operand::Immediate(x), _) => x.to_string(),
+            (Operand::Position(x), _) => format!("mem[{}]", x),
+            (Operand::Relative(x), Some(base)) => match base.checked_add(x) {
+                Some(slot) => self.slot(slot),
+                None => format!("rel[{}]", x),
+            },
+            (Operand::Relative(x), None) => format!("rel[{}]", x),
+        }
+    }
+
+    // Names the cell at slot relative to the base the function was called
+    // with.
+    fn slot(&self, slot: i64) -> String {
+        let frame = match self.frame {
+            Some(x) => x,
+            None => return format!("mem[{}]", slot),
+        };
+        match slot {
+            0 => "ret".to_string(),
+            x if x > 0 && x < frame => format!("arg{}", x - 1),
+            x if x > frame => format!("out{}", x - frame - 1),
+            x => format!("stack[{}]", x),
+        }
+    }
+
+    fn block_code(&self, block: &Block, mut base: Option<i64>, flags: &HashSet<i64>) -> BlockCode {
+        let insts = &block.instructions;
+        let last = insts.last().unwrap();
+
+        // The jump ending the block, and for a call the push before it, are
+        // written as part of the exit.
+        let mut body = match block.exit {
+            Exit::Call { .. } | Exit::IndirectCall { .. } => &insts[..insts.len() - 2],
+            _ if cfg::is_jump(last) => &insts[..insts.len() - 1],
+            _ => &insts[..],
+        };
+
+        // A jump on the result of the comparison just before it tests the
+        // comparison itself. The result needn't be kept if nothing else
+        // reads it.
+        let mut compare = None;
+        if cfg::is_jump(last) && insts.len() >= 2 && fuses(&insts[insts.len() - 2], last) {
+            compare = Some(&insts[insts.len() - 2]);
+            match last.operands()[0] {
+                Operand::Position(x) if flags.contains(&x) => body = &body[..body.len() - 1],
+                _ => (),
+            }
+        }
+
+        let mut stmts = Vec::new();
+        for inst in body {
+            let after = adjust(base, inst);
+            stmts.extend(self.statement(inst, base, after).map(Stmt::Line));
+            base = after;
+        }
+
+        let operands = last.operands();
+        let if_true = matches!(last.opcode, Opcode::JumpIfTrue(..));
+        let cond = match block.exit {
+            Exit::Branch { .. } | Exit::Indirect { cond: Some(_), .. } => Some(match compare {
+                Some(inst) => {
+                    let ops = inst.operands();
+                    let (a, b) = (self.operand(&ops[0], base), self.operand(&ops[1], base));
+                    let (truthy, falsy) = if let Opcode::LessThan(..) = inst.opcode {
+                        ("<", ">=")
+                    } else {
+                        ("==", "!=")
+                    };
+                    Cond::new(
+                        format!("{} {} {}", a, truthy, b),
+                        format!("{} {} {}", a, falsy, b),
+                        if_true,
+                    )
+                }
+                None => {
+                    let x = self.operand(&operands[0], base);
+                    Cond::new(x.clone(), format!("!{}", x), if_true)
+                }
+            }),
+            _ => None,
+        };
+        let target = match block.exit {
+            Exit::Indirect { .. } | Exit::IndirectCall { .. } => {
+                Some(self.operand(&operands[1], base))
+            }
+            _ => None,
+        };
+
+        BlockCode {
+            stmts,
+            cond,
+            target,
+        }
+    }
+
+    // Renders an instruction that doesn't jump. base is the relative base
+    // before it runs and after the base once it has.
+    fn statement(
+        &self,
+        inst: &Instruction,
+        base: Option<i64>,
+        after: Option<i64>,
+    ) -> Option<String> {
+        let ops = inst.operands();
+        let arg = |i: usize| self.operand(&ops[i], base);
+        let value = match inst.opcode {
+            Opcode::Add(..) => match (ops[0], ops[1]) {
+                (Operand::Immediate(0), _) => arg(1),
+                (_, Operand::Immediate(0)) => arg(0),
+                (_, Operand::Immediate(x)) if x < 0 && x != i64::MIN => {
+                    format!("{} - {}", arg(0), -x)
+                }
+                _ => format!("{} + {}", arg(0), arg(1)),
+            },
+            Opcode::Mul(..) => match (ops[0], ops[1]) {
+                (Operand::Immediate(1), _) => arg(1),
+                (_, Operand::Immediate(1)) => arg(0),
+                _ => format!("{} * {}", arg(0), arg(1)),
+            },
+            Opcode::LessThan(..) => format!("{} < {}", arg(0), arg(1)),
+            Opcode::Equals(..) => format!("{} == {}", arg(0), arg(1)),
+            Opcode::Input(..) => "input()".to_string(),
+            Opcode::Output(..) => return Some(format!("output({});", arg(0))),
+            Opcode::AdjustRelativeBase(..) => {
+                // Known adjustments only show up once the base is lost,
+                // and then as the total since the function began.
+                return match (base, after) {
+                    (Some(_), Some(_)) => None,
+                    (Some(b), None) if b != 0 => Some(format!("rel += {} + {};", b, arg(0))),
+                    _ => Some(format!("rel += {};", arg(0))),
+                };
+            }
+            Opcode::Custom(..) => unreachable!("parse_opcode has no custom opcodes"),
+            Opcode::JumpIfTrue(..) | Opcode::JumpIfFalse(..) | Opcode::Halt => return None,
+        };
+
+        let output = inst.opcode.output_operand().unwrap();
+        Some(format!("{} = {};", arg(output), value))
+    }
+}
+
+// Applies inst to a relative base known to be base, if known.
+fn adjust(base: Option<i64>, inst: &Instruction) -> Option<i64> {
+    match (inst.opcode, inst.operands().first()) {
+        (Opcode::AdjustRelativeBase(_), Some(&Operand::Immediate(x))) => {
+            base.and_then(|b| b.checked_add(x))
+        }
+        (Opcode::AdjustRelativeBase(_), _) => None,
+        _ => base,
+    }
+}
+
+// Finds the relative base at the start of each block in function, as an
+// offset from the base it was called with, where every path agrees on it.
+// Calls are assumed to leave the base as they found it.
+fn bases(cfg: &Cfg, function: &Function) -> HashMap<usize, Option<i64>> {
+    let mut bases = HashMap::new();
+    bases.insert(function.entry, Some(0));
+    let mut work = vec![function.entry];
+
+    while let Some(start) = work.pop() {
+        let block = &cfg.blocks[&start];
+        let base = block.instructions.iter().fold(bases[&start], adjust);
+        for next in block.exit.successors() {
+            if !cfg.blocks.contains_key(&next) {
+                continue;
+            }
+            let merged = match bases.get(&next) {
+                None => base,
+                Some(&old) if old == base => continue,
+                Some(_) => None,
+            };
+            bases.insert(next, merged);
+            work.push(next);
+        }
+    }
+    bases
+}
+
+// Whether jump tests the result of prev, a comparison.
+fn fuses(prev: &Instruction, jump: &Instruction) -> bool {
+    matches!(prev.opcode, Opcode::LessThan(..) | Opcode::Equals(..))
+        && prev.operands()[2] == jump.operands()[0]
+}
+
+// Finds cells, like the scratch cell compilers use for conditions, that
+// are only ever read by a jump right after a comparison stores to them.
+fn flag_cells(cfg: &Cfg) -> HashSet<i64> {
+    let mut flags = HashSet::new();
+    let mut others = HashSet::new();
+    for block in cfg.blocks.values() {
+        for (i, inst) in block.instructions.iter().enumerate() {
+            let output = inst.opcode.output_operand();
+            for (j, op) in inst.operands().into_iter().enumerate() {
+                if let (Operand::Position(x), false) = (op, output == Some(j)) {
+                    let fused = j == 0
+                        && i > 0
+                        && cfg::is_jump(inst)
+                        && fuses(&block.instructions[i - 1], inst);
+                    if fused {
+                        flags.insert(x);
+                    } else {
+                        others.insert(x);
+                    }
+                }
+            }
+        }
+    }
+    flags.difference(&others).cloned().collect()
+}
+
+fn goto_targets(stmts: &[Stmt], targets: &mut HashSet<usize>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Goto(x) => {
+                targets.insert(*x);
+            }
+            Stmt::If {
+                then, otherwise, ..
+            } => {
+                goto_targets(then, targets);
+                goto_targets(otherwise, targets);
+            }
+            Stmt::While { body, .. } | Stmt::DoWhile { body, .. } => goto_targets(body, targets),
+            Stmt::Line(_) | Stmt::Label(_) | Stmt::Break => (),
+        }
+    }
+}
+
+// Writes stmts indented by depth levels. Only labels that are jumped to are
+// written.
+fn write_stmts(out: &mut String, stmts: &[Stmt], depth: usize, labels: &HashSet<usize>) {
+    let indent = "    ".repeat(depth);
+    for stmt in stmts {
+        match stmt {
+            Stmt::Line(x) => writeln!(out, "{}{}", indent, x).unwrap(),
+            Stmt::Label(x) if labels.contains(x) => {
+                writeln!(out, "{}L{}:", indent.get(4..).unwrap_or(""), x).unwrap()
+            }
+            Stmt::Label(_) => (),
+            Stmt::Goto(x) => writeln!(out, "{}goto L{};", indent, x).unwrap(),
+            Stmt::Break => writeln!(out, "{}break;", indent).unwrap(),
+            Stmt::If {
+                cond,
+                then,
+                otherwise,
+            } => {
+                if let ([Stmt::Line(_) | Stmt::Goto(_) | Stmt::Break], true) =
+                    (&then[..], otherwise.is_empty())
+                {
+                    write!(out, "{}if ({}) ", indent, cond).unwrap();
+                    write_stmts(out, then, 0, labels);
+                    continue;
+                }
+                writeln!(out, "{}if ({}) {{", indent, cond).unwrap();
+                write_stmts(out, then, depth + 1, labels);
+                if !otherwise.is_empty() {
+                    writeln!(out, "{}}} else {{", indent).unwrap();
+                    write_stmts(out, otherwise, depth + 1, labels);
+                }
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+            Stmt::While { cond, body } => {
+                writeln!(out, "{}while ({}) {{", indent, cond).unwrap();
+                write_stmts(out, body, depth + 1, labels);
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+            Stmt::DoWhile { body, cond } => {
+                writeln!(out, "{}do {{", indent).unwrap();
+                write_stmts(out, body, depth + 1, labels);
+                writeln!(out, "{}}} while ({});", indent, cond).unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    #[test]
+    fn test_decompile() {
+        // Outputs the squares of n down to 1, capped at 100.
+        let program = asm::assemble(
+            "
+                arb #stack
+                in [n]
+            loop:
+                jf [n], #done
+                add [n], #0, rel[1]
+                add #ret, #0, rel[0]
+                jt #1, #square
+            ret:
+                out rel[1]
+                add [n], #-1, [n]
+                jt #1, #loop
+            done:
+                hlt
+            square:
+                arb #2
+                mul rel[-1], rel[-1], rel[-1]
+                lt rel[-1], #100, [flag]
+                jt [flag], #small
+                add #100, #0, rel[-1]
+            small:
+                arb #-2
+                jt #1, rel[0]
+            n: db 0
+            flag: db 0
+            stack: db 0
+            ",
+        )
+        .unwrap();
+
+        let expected = "void main() {
+    mem[50] = input();
+    while (mem[50]) {
+        mem[53] = mem[50];
+        f28();
+        output(mem[53]);
+        mem[50] = mem[50] - 1;
+    }
+    halt();
+}
+
+void f28() {
+    arg0 = arg0 * arg0;
+    if (arg0 >= 100) {
+        arg0 = 100;
+    }
+    return;
+}
+";
+        assert_eq!(decompile(&program), expected);
+    }
+
+    #[test]
+    fn test_unknown_base() {
+        let program = asm::assemble(
+            "
+            loop:
+                in [x]
+                out [x]
+                jt [x], #loop
+                arb #5
+                arb [x]
+                out rel[0]
+                hlt
+            x: db 0
+            ",
+        )
+        .unwrap();
+
+        let expected = "void main() {
+    do {
+        mem[14] = input();
+        output(mem[14]);
+    } while (mem[14]);
+    rel += 5 + mem[14];
+    output(rel[0]);
+    halt();
+}
+";
+        assert_eq!(decompile(&program), expected);
+    }
+}
@@ -0,0 +1,294 @@
+use super::{parse_opcode, Error, Opcode, ParameterMode};
+
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Position(i64),
+    Immediate(i64),
+    Relative(i64),
+}
+
+impl Operand {
+    pub fn new(mode: ParameterMode, value: i64) -> Operand {
+        match mode {
+            ParameterMode::Position => Operand::Position(value),
+            ParameterMode::Immediate => Operand::Immediate(value),
+            ParameterMode::Relative => Operand::Relative(value),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Position(x) => write!(f, "[{}]", x),
+            Operand::Immediate(x) => write!(f, "#{}", x),
+            Operand::Relative(x) => write!(f, "rel[{}]", x),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    pub address: usize,
+    pub opcode: Opcode,
+    // Every cell of the instruction, starting with the opcode.
+    pub cells: Vec<i64>,
+}
+
+impl Instruction {
+    // Decodes the instruction at address. cells starts with the opcode; any
+    // cells missing from the end are read as 0.
+    pub fn decode(address: usize, cells: &[i64]) -> Result<Instruction, Error> {
+        let first = cells.first().cloned().unwrap_or(0);
+        let opcode = parse_opcode(address, first)?;
+        let cells = (0..opcode.size())
+            .map(|i| cells.get(i).cloned().unwrap_or(0))
+            .collect();
+
+        Ok(Instruction {
+            address,
+            opcode,
+            cells,
+        })
+    }
+
+    pub fn operands(&self) -> Vec<Operand> {
+        self.opcode
+            .modes()
+            .into_iter()
+            .zip(self.cells[1..].iter())
+            .map(|(m, &x)| Operand::new(m, x))
+            .collect()
+    }
+
+    pub fn size(&self) -> usize {
+        self.cells.len()
+    }
+
+    // Returns the jump target if this is a jump with an immediate target.
+    pub fn jump_target(&self) -> Option<usize> {
+        match self.opcode {
+            Opcode::JumpIfTrue(_, ParameterMode::Immediate)
+            | Opcode::JumpIfFalse(_, ParameterMode::Immediate)
+                if self.cells[2] >= 0 =>
+            {
+                Some(self.cells[2] as usize)
+            }
+            _ => None,
+        }
+    }
+
+    // Returns false if execution can never continue to the next instruction:
+    // halts and jumps whose condition is an immediate that always jumps.
+    pub fn falls_through(&self) -> bool {
+        match self.opcode {
+            Opcode::Halt => false,
+            Opcode::JumpIfTrue(ParameterMode::Immediate, _) => self.cells[1] == 0,
+            Opcode::JumpIfFalse(ParameterMode::Immediate, _) => self.cells[1] != 0,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operands: Vec<_> = self.operands().iter().map(|x| x.to_string()).collect();
+        if operands.is_empty() {
+            write!(f, "{}", self.opcode.mnemonic())
+        } else {
+            write!(f, "{} {}", self.opcode.mnemonic(), operands.join(", "))
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Line {
+    Code(Instruction),
+    Data { address: usize, value: i64 },
+}
+
+impl Line {
+    pub fn address(&self) -> usize {
+        match self {
+            Line::Code(inst) => inst.address,
+            Line::Data { address, .. } => *address,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Code(inst) => {
+                let cells: Vec<_> = inst.cells.iter().map(|x| x.to_string()).collect();
+                write!(f, "{:>6}: {:<32} {}", inst.address, cells.join(" "), inst)
+            }
+            Line::Data { address, value } => {
+                write!(f, "{:>6}: {:<32} db {}", address, value, value)
+            }
+        }
+    }
+}
+
+// Splits program into code and data. Code is found by following execution
+// from address 0 through fall-throughs, immediate jump targets, and constants
+// that look like return addresses being pushed (add #addr, #0, x or
+// mul #addr, #1, x). Anything not reached this way is treated as data.
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    let starts = find_code(program);
+
+    let mut ret = Vec::new();
+    let mut i = 0;
+    while i < program.len() {
+        match &starts[i] {
+            Some(inst) => {
+                i += inst.size();
+                ret.push(Line::Code(inst.clone()));
+            }
+            None => {
+                ret.push(Line::Data {
+                    address: i,
+                    value: program[i],
+                });
+                i += 1;
+            }
+        }
+    }
+
+    ret
+}
+
+// Renders the disassembly of program, one line per instruction or data cell.
+pub fn listing(program: &[i64]) -> String {
+    disassemble(program)
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// Returns the instruction starting at each address, if any.
+fn find_code(program: &[i64]) -> Vec<Option<Instruction>> {
+    let mut starts = vec![None; program.len()];
+    let mut claimed = vec![false; program.len()];
+    let mut work = vec![0];
+
+    while let Some(address) = work.pop() {
+        if address >= program.len() || claimed[address] {
+            continue;
+        }
+
+        let inst = match Instruction::decode(address, &program[address..]) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+
+        let end = address + inst.size();
+        if end > program.len() || claimed[address..end].iter().any(|&x| x) {
+            continue;
+        }
+        claimed[address..end].iter_mut().for_each(|x| *x = true);
+
+        if inst.falls_through() {
+            work.push(end);
+        }
+        if let Some(target) = inst.jump_target() {
+            work.push(target);
+        }
+        if let Some(target) = return_address(&inst) {
+            work.push(target);
+        }
+
+        starts[address] = Some(inst);
+    }
+
+    starts
+}
+
+// Recognizes an immediate constant being copied somewhere (add #x, #0 or
+// mul #x, #1), which in compiled puzzle programs is usually a return address
+// being pushed before a call.
+pub(super) fn return_address(inst: &Instruction) -> Option<usize> {
+    let identity = match inst.opcode {
+        Opcode::Add(ParameterMode::Immediate, ParameterMode::Immediate, _) => 0,
+        Opcode::Mul(ParameterMode::Immediate, ParameterMode::Immediate, _) => 1,
+        _ => return None,
+    };
+
+    let value = if inst.cells[2] == identity {
+        inst.cells[1]
+    } else if inst.cells[1] == identity {
+        inst.cells[2]
+    } else {
+        return None;
+    };
+
+    if value > 0 {
+        Some(value as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instruction() {
+        let inst = Instruction::decode(7, &[21101, 10, 5, 3]).unwrap();
+        assert_eq!(inst.to_string(), "add #10, #5, rel[3]");
+        assert_eq!(inst.size(), 4);
+
+        let inst = Instruction::decode(0, &[1005, 10, -1]).unwrap();
+        assert_eq!(inst.to_string(), "jt [10], #-1");
+        assert_eq!(inst.jump_target(), None);
+
+        assert_eq!(Instruction::decode(0, &[99]).unwrap().to_string(), "hlt");
+        assert_eq!(
+            Instruction::decode(3, &[42]),
+            Err(Error::BadOpcode { pc: 3, opcode: 42 })
+        );
+    }
+
+    #[test]
+    fn test_disassemble() {
+        // Jumps over a data cell, then pushes return address 12 before
+        // jumping to 14.
+        let program = vec![
+            1105, 1, 4, 77, 21101, 12, 0, 0, 1105, 1, 14, 55, 99, 0, 4, 3, 99,
+        ];
+        let lines = disassemble(&program);
+        let addresses: Vec<_> = lines
+            .iter()
+            .filter_map(|x| match x {
+                Line::Code(inst) => Some(inst.address),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(addresses, vec![0, 4, 8, 12, 14, 16]);
+        assert_eq!(
+            lines[1],
+            Line::Data {
+                address: 3,
+                value: 77
+            }
+        );
+    }
+
+    #[test]
+    fn test_listing() {
+        let expected = "     0: 1 9 10 3                         add [9], [10], [3]
+     4: 2 3 11 0                         mul [3], [11], [0]
+     8: 99                               hlt
+     9: 30                               db 30
+    10: 40                               db 40
+    11: 50                               db 50";
+        assert_eq!(
+            listing(&[1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]),
+            expected
+        );
+    }
+}
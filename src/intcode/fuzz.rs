@@ -0,0 +1,503 @@
+use super::compile::{Compiled, CompiledComputer};
+use super::{disasm, Budget, Budgeted, Computer, Error, State};
+
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+// Instructions a generated program may run before it counts as looping.
+const BUDGET: u64 = 2_000;
+
+// How long a path without a budget may run before it counts as hung. Far
+// longer than BUDGET instructions take.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Instructions a resumable run executes between calls to execute_with_budget.
+const CHUNK: u64 = 7;
+
+// A splitmix64 generator, so a seed always produces the same programs.
+#[derive(Clone, Debug)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A value in lo..hi, which must not be empty.
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.next_u64() % (hi - lo) as u64) as i64
+    }
+
+    // True with probability 1 in n.
+    pub fn one_in(&mut self, n: u64) -> bool {
+        self.next_u64().is_multiple_of(n)
+    }
+}
+
+// Generates a random program that only uses valid opcodes and modes and
+// never writes to an immediate operand. Addresses mostly fall inside the
+// program, so it reads and overwrites its own code and data, and jumps may
+// land anywhere, including in the middle of an instruction.
+pub fn generate_program(rng: &mut Rng) -> Vec<i64> {
+    let len = rng.range(8, 48) as usize;
+    let code = len - rng.range(1, 6) as usize;
+
+    let mut program = Vec::with_capacity(len);
+    while program.len() < code {
+        let opcode = match rng.range(0, 20) {
+            0..=2 => 1,
+            3..=5 => 2,
+            6..=7 => 3,
+            8..=10 => 4,
+            11..=12 => 5,
+            13..=14 => 6,
+            15..=16 => 7,
+            17 => 8,
+            18 => 9,
+            _ => 99,
+        };
+        let (arity, output) = match opcode {
+            1 | 2 | 7 | 8 => (3, Some(3)),
+            3 => (1, Some(1)),
+            4 | 9 => (1, None),
+            5 | 6 => (2, None),
+            _ => (0, None),
+        };
+
+        let mut operands = Vec::new();
+        let mut instruction = opcode;
+        for i in 1..=arity {
+            let mode = match output {
+                Some(o) if o == i => rng.range(0, 2) * 2,
+                _ => rng.range(0, 3),
+            };
+            let jump = matches!(opcode, 5 | 6) && i == 2;
+            let operand = match mode {
+                0 => rng.range(0, len as i64 + 4),
+                1 if jump => rng.range(0, len as i64),
+                1 if rng.one_in(20) => rng.next_u64() as i64,
+                1 => rng.range(-5, 10),
+                _ => rng.range(-4, len as i64),
+            };
+            instruction += mode * 10i64.pow(i as u32 + 1);
+            operands.push(operand);
+        }
+        program.push(instruction);
+        program.extend(operands);
+    }
+    while program.len() < len {
+        program.push(rng.range(-3, 10));
+    }
+    program
+}
+
+// Generates up to three inputs for a program.
+pub fn generate_inputs(rng: &mut Rng) -> Vec<i64> {
+    let n = rng.range(0, 4);
+    (0..n).map(|_| rng.range(-3, 10)).collect()
+}
+
+// A way of running a program. Every path must behave exactly like
+// Reference, which decodes each instruction from scratch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Path {
+    Reference,
+    // The decode cache, run under execute_with_budget.
+    Cached,
+    // execute_with_budget resumed every CHUNK instructions.
+    Chunked,
+    // One instruction at a time with step.
+    Stepped,
+    // Plain execute, which has no budget.
+    Execute,
+    // CompiledComputer, which has no budget either.
+    Compiled,
+}
+
+impl Path {
+    pub const ALL: [Path; 6] = [
+        Path::Reference,
+        Path::Cached,
+        Path::Chunked,
+        Path::Stepped,
+        Path::Execute,
+        Path::Compiled,
+    ];
+
+    // Whether the path stops after BUDGET instructions. Paths that don't are
+    // only run on programs that finish within it.
+    pub fn bounded(self) -> bool {
+        !matches!(self, Path::Execute | Path::Compiled)
+    }
+
+    // Runs program, feeding inputs as they are requested.
+    pub fn run(self, program: &[i64], inputs: &[i64]) -> Outcome {
+        if self == Path::Compiled {
+            let mut cpu = CompiledComputer::new(Rc::new(Compiled::new(program)));
+            let (outputs, end) = drive(&mut cpu, inputs, |cpu, _| {
+                cpu.execute().map(Budgeted::Stopped)
+            });
+            return Outcome::new(
+                outputs,
+                end,
+                cpu.pc(),
+                cpu.relative_base(),
+                cpu.ram().iter(),
+            );
+        }
+
+        let mut cpu = Computer::new(program);
+        if self == Path::Reference {
+            cpu.set_decode_cache(false);
+        }
+        let run = |cpu: &mut Computer, budget: u64| match self {
+            Path::Reference | Path::Cached => cpu.execute_with_budget(Budget {
+                instructions: Some(budget),
+                deadline: None,
+            }),
+            Path::Chunked => {
+                let end = cpu.instruction_count() + budget;
+                loop {
+                    let remaining = end - cpu.instruction_count();
+                    let chunk = Budget {
+                        instructions: Some(remaining.min(CHUNK)),
+                        deadline: None,
+                    };
+                    match cpu.execute_with_budget(chunk)? {
+                        Budgeted::Exhausted if remaining > CHUNK => continue,
+                        result => return Ok(result),
+                    }
+                }
+            }
+            Path::Stepped => {
+                let end = cpu.instruction_count() + budget;
+                while cpu.instruction_count() < end {
+                    if let Some(state) = cpu.step()? {
+                        return Ok(Budgeted::Stopped(state));
+                    }
+                }
+                Ok(Budgeted::Exhausted)
+            }
+            Path::Execute => cpu.execute().map(Budgeted::Stopped),
+            Path::Compiled => unreachable!(),
+        };
+        let (outputs, end) = drive(&mut cpu, inputs, run);
+        Outcome::new(outputs, end, cpu.pc(), cpu.relative_base(), cpu.ram.iter())
+    }
+
+    // Like run, but catches panics and gives up on paths without a budget
+    // after TIMEOUT. Returns None if the run panicked or hung.
+    pub fn try_run(self, program: &[i64], inputs: &[i64]) -> Option<Outcome> {
+        if self.bounded() {
+            return panic::catch_unwind(AssertUnwindSafe(|| self.run(program, inputs))).ok();
+        }
+
+        // A hung thread can't be stopped, so it is left to run in the
+        // background.
+        let (tx, rx) = mpsc::channel();
+        let (program, inputs) = (program.to_vec(), inputs.to_vec());
+        thread::spawn(move || {
+            let _ = tx.send(self.run(&program, &inputs));
+        });
+        rx.recv_timeout(TIMEOUT).ok()
+    }
+}
+
+// Runs a machine until it stops for something other than output or input
+// it has been given, within BUDGET instructions in total.
+fn drive<C, R>(cpu: &mut C, inputs: &[i64], mut run: R) -> (Vec<i64>, Result<Budgeted, Error>)
+where
+    C: Driven,
+    R: FnMut(&mut C, u64) -> Result<Budgeted, Error>,
+{
+    let mut outputs = Vec::new();
+    let mut inputs = inputs.iter();
+    loop {
+        let budget = BUDGET.saturating_sub(cpu.instruction_count());
+        match run(cpu, budget) {
+            Ok(Budgeted::Stopped(State::Output(x))) => outputs.push(x),
+            Ok(Budgeted::Stopped(State::InputRequested)) => match inputs.next() {
+                Some(&x) => {
+                    if let Err(e) = cpu.input(x) {
+                        return (outputs, Err(e));
+                    }
+                }
+                None => return (outputs, Ok(Budgeted::Stopped(State::InputRequested))),
+            },
+            end => return (outputs, end),
+        }
+    }
+}
+
+// What drive needs from the machines it runs.
+trait Driven {
+    fn instruction_count(&self) -> u64;
+    fn input(&mut self, x: i64) -> Result<(), Error>;
+}
+
+impl Driven for Computer {
+    fn instruction_count(&self) -> u64 {
+        Computer::instruction_count(self)
+    }
+
+    fn input(&mut self, x: i64) -> Result<(), Error> {
+        Computer::input(self, x)
+    }
+}
+
+impl Driven for CompiledComputer {
+    fn instruction_count(&self) -> u64 {
+        CompiledComputer::instruction_count(self)
+    }
+
+    fn input(&mut self, x: i64) -> Result<(), Error> {
+        CompiledComputer::input(self, x)
+    }
+}
+
+// Everything a run can observably do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub outputs: Vec<i64>,
+    // Why the run stopped: halted, waiting for input it wasn't given, out of
+    // budget, or an error.
+    pub end: Result<Budgeted, Error>,
+    pub pc: usize,
+    pub relative_base: i64,
+    // Nonzero cells of ram, in address order.
+    pub ram: Vec<(usize, i64)>,
+}
+
+impl Outcome {
+    fn new<I>(
+        outputs: Vec<i64>,
+        end: Result<Budgeted, Error>,
+        pc: usize,
+        relative_base: i64,
+        ram: I,
+    ) -> Outcome
+    where
+        I: Iterator<Item = (usize, i64)>,
+    {
+        Outcome {
+            outputs,
+            end,
+            pc,
+            relative_base,
+            ram: ram.filter(|&(_, x)| x != 0).collect(),
+        }
+    }
+}
+
+// A program and inputs on which path behaves differently from
+// Path::Reference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>,
+    pub path: Path,
+    pub expected: Outcome,
+    // None if the path panicked or hung.
+    pub actual: Option<Outcome>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |xs: &[i64]| {
+            let xs: Vec<_> = xs.iter().map(|x| x.to_string()).collect();
+            xs.join(",")
+        };
+        writeln!(f, "{:?} diverges from {:?}", self.path, Path::Reference)?;
+        writeln!(f, "program: {}", join(&self.program))?;
+        writeln!(f, "inputs: {}", join(&self.inputs))?;
+        for line in disasm::disassemble(&self.program) {
+            writeln!(f, "    {}", line)?;
+        }
+        writeln!(f, "expected: {:?}", self.expected)?;
+        match &self.actual {
+            Some(actual) => write!(f, "actual: {:?}", actual),
+            None => write!(f, "actual: panicked or hung"),
+        }
+    }
+}
+
+// Runs program on every path and compares each against Path::Reference.
+// Paths without a budget are skipped if the reference runs out of budget.
+pub fn check(program: &[i64], inputs: &[i64]) -> Option<Divergence> {
+    let expected = Path::Reference.run(program, inputs);
+    Path::ALL[1..]
+        .iter()
+        .find_map(|&path| check_path(program, inputs, path, &expected))
+}
+
+fn check_path(
+    program: &[i64],
+    inputs: &[i64],
+    path: Path,
+    expected: &Outcome,
+) -> Option<Divergence> {
+    if !path.bounded() && expected.end == Ok(Budgeted::Exhausted) {
+        return None;
+    }
+    let actual = path.try_run(program, inputs);
+    if actual.as_ref() == Some(expected) {
+        return None;
+    }
+    Some(Divergence {
+        program: program.to_vec(),
+        inputs: inputs.to_vec(),
+        path,
+        expected: expected.clone(),
+        actual,
+    })
+}
+
+// Checks count random programs generated from seed. Returns the first
+// divergence found, minimized.
+pub fn fuzz(seed: u64, count: usize) -> Option<Divergence> {
+    let mut rng = Rng::new(seed);
+    let divergence = (0..count).find_map(|_| {
+        let program = generate_program(&mut rng);
+        let inputs = generate_inputs(&mut rng);
+        check(&program, &inputs)
+    })?;
+
+    let path = divergence.path;
+    let diverges = |program: &[i64], inputs: &[i64]| {
+        let expected = Path::Reference.run(program, inputs);
+        check_path(program, inputs, path, &expected)
+    };
+    let (program, inputs) = minimize(divergence.program, divergence.inputs, |p, i| {
+        diverges(p, i).is_some()
+    });
+    diverges(&program, &inputs)
+}
+
+// Shrinks a failing program and inputs for as long as fails still holds:
+// drops inputs, truncates the program, deletes runs of cells, and zeroes
+// or halves single cells. Stops when no single change keeps it failing.
+pub fn minimize<F>(mut program: Vec<i64>, mut inputs: Vec<i64>, fails: F) -> (Vec<i64>, Vec<i64>)
+where
+    F: Fn(&[i64], &[i64]) -> bool,
+{
+    'shrink: loop {
+        for (p, i) in candidates(&program, &inputs) {
+            if fails(&p, &i) {
+                program = p;
+                inputs = i;
+                continue 'shrink;
+            }
+        }
+        return (program, inputs);
+    }
+}
+
+// Every program and inputs one step smaller than the given ones, roughly
+// from biggest step to smallest.
+fn candidates(program: &[i64], inputs: &[i64]) -> Vec<(Vec<i64>, Vec<i64>)> {
+    let mut candidates = Vec::new();
+    for i in 0..inputs.len() {
+        let mut inputs = inputs.to_vec();
+        inputs.remove(i);
+        candidates.push((program.to_vec(), inputs));
+    }
+    for len in 0..program.len() {
+        candidates.push((program[..len].to_vec(), inputs.to_vec()));
+    }
+    // Instructions take up to four cells, so deleting one whole keeps the
+    // rest of the code intact.
+    for n in (1..=4).rev() {
+        for i in 0..program.len().saturating_sub(n - 1) {
+            let mut p = program.to_vec();
+            p.drain(i..i + n);
+            candidates.push((p, inputs.to_vec()));
+        }
+    }
+    for i in 0..program.len() {
+        for x in [0, program[i] / 2] {
+            if x != program[i] {
+                let mut p = program.to_vec();
+                p[i] = x;
+                candidates.push((p, inputs.to_vec()));
+            }
+        }
+    }
+    for i in 0..inputs.len() {
+        for x in [0, inputs[i] / 2] {
+            if x != inputs[i] {
+                let mut inputs = inputs.to_vec();
+                inputs[i] = x;
+                candidates.push((program.to_vec(), inputs));
+            }
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fixed so failures reproduce. Try other seeds locally to search further.
+    const SEED: u64 = 0x1dc0de;
+
+    #[test]
+    fn test_fuzz() {
+        if let Some(divergence) = fuzz(SEED, 5_000) {
+            panic!("{}", divergence);
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let mut rng = Rng::new(SEED);
+        for _ in 0..100 {
+            let program = generate_program(&mut rng);
+            let mut cpu = Computer::new(&program);
+            // Generated code never fails to decode, so the first instruction
+            // either runs or fails on an address.
+            match cpu.step() {
+                Err(Error::BadOpcode { .. })
+                | Err(Error::BadParameterMode { .. })
+                | Err(Error::ImmediateWrite { .. }) => panic!("invalid program {:?}", program),
+                _ => {}
+            }
+        }
+        assert_eq!(
+            generate_program(&mut Rng::new(1)),
+            generate_program(&mut Rng::new(1))
+        );
+    }
+
+    #[test]
+    fn test_minimize() {
+        // Outputs 6 * 7 between some irrelevant arithmetic.
+        let program = [
+            1101, 5, 3, 20, 1102, 6, 7, 19, 4, 19, 1001, 20, 1, 20, 99, 0, 0, 0, 0, 0, 0,
+        ];
+        let outputs_42 = |program: &[i64], inputs: &[i64]| {
+            Path::Reference.run(program, inputs).outputs.contains(&42)
+        };
+        assert!(outputs_42(&program, &[9, 4]));
+
+        let (program, inputs) = minimize(program.to_vec(), vec![9, 4], outputs_42);
+        assert!(outputs_42(&program, &inputs));
+        assert_eq!(program, [1102, 6, 7, 19, 4, 19]);
+        assert!(inputs.is_empty());
+        assert!(candidates(&program, &inputs)
+            .iter()
+            .all(|(p, i)| !outputs_42(p, i)));
+    }
+}
@@ -0,0 +1,160 @@
+use super::{Computer, Word};
+
+use std::collections::VecDeque;
+
+// Undo log for stepping a Computer backwards. Only the most recent capacity
+// instructions are kept.
+pub(super) struct History<W> {
+    entries: VecDeque<Entry<W>>,
+    capacity: usize,
+}
+
+// State needed to undo one instruction.
+struct Entry<W> {
+    pc: usize,
+    relative_base: i64,
+    // Address written and the value it held before.
+    overwritten: Option<(usize, W)>,
+    // Whether the instruction was an input or output.
+    io: bool,
+}
+
+impl<W: Word> Computer<W> {
+    // Starts recording the last capacity executed instructions so they can
+    // be undone with step_back and rewind_to_io. Clears any existing history.
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History {
+            entries: VecDeque::new(),
+            capacity,
+        });
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    // Number of instructions that can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |h| h.entries.len())
+    }
+
+    // Undoes up to n instructions. Returns the number undone.
+    pub fn step_back(&mut self, n: usize) -> usize {
+        for i in 0..n {
+            if self.undo().is_none() {
+                return i;
+            }
+        }
+        n
+    }
+
+    // Undoes instructions until just before the most recent input or output
+    // instruction. If no input or output is in the history, undoes all of
+    // it. Returns the number of instructions undone.
+    pub fn rewind_to_io(&mut self) -> usize {
+        let mut count = 0;
+        while let Some(io) = self.undo() {
+            count += 1;
+            if io {
+                break;
+            }
+        }
+        count
+    }
+
+    // Records an instruction that just executed from pc with relative_base.
+    pub(super) fn record(&mut self, pc: usize, relative_base: i64, io: bool) {
+        let overwritten = self.overwritten.take();
+        if let Some(history) = self.history.as_mut() {
+            if history.capacity == 0 {
+                return;
+            }
+            if history.entries.len() == history.capacity {
+                history.entries.pop_front();
+            }
+            history.entries.push_back(Entry {
+                pc,
+                relative_base,
+                overwritten,
+                io,
+            });
+        }
+    }
+
+    // Undoes the last recorded instruction. Returns whether it was an input
+    // or output, or None if there is nothing to undo.
+    fn undo(&mut self) -> Option<bool> {
+        let entry = self.history.as_mut()?.entries.pop_back()?;
+        self.instructions -= 1;
+        self.pc = entry.pc;
+        self.relative_base = entry.relative_base;
+        if let Some((address, value)) = entry.overwritten {
+            // The cell was already written once, so it is allocated and
+            // restoring it cannot exceed the memory limit.
+            let restored = self.ram.set(address, value);
+            assert!(restored, "undo exceeded the memory limit");
+        }
+        Some(entry.io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, State};
+
+    // Reads a number, then counts it down to 0, outputting each value.
+    const PROGRAM: [i64; 14] = [3, 13, 4, 13, 1001, 13, -1, 13, 1005, 13, 2, 99, 0, 0];
+
+    #[test]
+    fn test_step_back() {
+        let mut cpu = Computer::new(&PROGRAM);
+        cpu.enable_history(100);
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        cpu.input(3).unwrap();
+        assert_eq!(cpu.execute(), Ok(State::Output(3)));
+        assert_eq!(cpu.execute(), Ok(State::Output(2)));
+        assert_eq!(cpu.history_len(), 5);
+
+        // Undo the output, the jump and the decrement.
+        assert_eq!(cpu.step_back(3), 3);
+        assert_eq!(cpu.instruction_count(), 2);
+        assert_eq!(cpu.pc(), 4);
+        assert_eq!(cpu.ram[13], 3);
+        assert_eq!(cpu.execute(), Ok(State::Output(2)));
+
+        assert_eq!(cpu.step_back(100), 5);
+        assert_eq!(cpu.pc(), 0);
+        assert_eq!(cpu.ram[13], 0);
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+    }
+
+    #[test]
+    fn test_rewind_to_io() {
+        let mut cpu = Computer::new(&PROGRAM);
+        cpu.enable_history(100);
+        cpu.execute().unwrap();
+        cpu.input(2).unwrap();
+        assert_eq!(cpu.execute(), Ok(State::Output(2)));
+        assert_eq!(cpu.execute(), Ok(State::Output(1)));
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+
+        assert_eq!(cpu.rewind_to_io(), 4);
+        assert_eq!(cpu.pc(), 2);
+        assert_eq!(cpu.ram[13], 1);
+        assert_eq!(cpu.execute(), Ok(State::Output(1)));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut cpu = Computer::new(&PROGRAM);
+        cpu.enable_history(2);
+        cpu.execute().unwrap();
+        cpu.input(5).unwrap();
+        cpu.execute().unwrap();
+        cpu.execute().unwrap();
+        assert_eq!(cpu.history_len(), 2);
+        assert_eq!(cpu.step_back(5), 2);
+        assert_eq!(cpu.pc(), 8);
+        assert_eq!(cpu.ram[13], 4);
+    }
+}
@@ -0,0 +1,139 @@
+use super::Word;
+
+use std::collections::HashMap;
+use std::ops::{Index, Range};
+
+// Addresses below this are stored in a Vec that grows on demand. Anything
+// higher is stored sparsely so a single far write doesn't allocate gigabytes.
+const DENSE_LIMIT: usize = 1 << 20;
+
+// Default maximum number of cells a program may use (128 MiB of i64s).
+pub const DEFAULT_LIMIT: usize = 1 << 24;
+
+// Intcode memory. Cells that have never been written read as 0.
+#[derive(Clone, Debug)]
+pub struct Memory<W = i64> {
+    dense: Vec<W>,
+    sparse: HashMap<usize, W>,
+    limit: usize,
+    // Returned for cells that have never been written.
+    zero: W,
+}
+
+impl<W: Word> Memory<W> {
+    // Creates memory holding program. The limit is raised to the program's
+    // length if the program wouldn't fit.
+    pub fn new(program: &[W], limit: usize) -> Memory<W> {
+        Memory::with_zero(program, limit, W::from_i64(0))
+    }
+}
+
+impl<W: Clone> Memory<W> {
+    // Like new, for values that aren't a Word. Cells that have never been
+    // written read as zero.
+    pub fn with_zero(program: &[W], limit: usize, zero: W) -> Memory<W> {
+        Memory {
+            dense: program.to_vec(),
+            sparse: HashMap::new(),
+            limit: limit.max(program.len()),
+            zero,
+        }
+    }
+
+    pub fn get(&self, address: usize) -> W {
+        self[address].clone()
+    }
+
+    // Writes value to address. Returns false if the write would make memory
+    // use more cells than its limit.
+    #[must_use]
+    pub fn set(&mut self, address: usize, value: W) -> bool {
+        if address < self.dense.len() {
+            self.dense[address] = value;
+        } else if address < DENSE_LIMIT {
+            if address + 1 + self.sparse.len() > self.limit {
+                return false;
+            }
+            self.dense.resize(address + 1, self.zero.clone());
+            self.dense[address] = value;
+        } else {
+            if !self.sparse.contains_key(&address) && self.cells() >= self.limit {
+                return false;
+            }
+            self.sparse.insert(address, value);
+        }
+
+        true
+    }
+
+    // Returns the values in range as a Vec.
+    pub fn range(&self, r: Range<usize>) -> Vec<W> {
+        r.map(|i| self.get(i)).collect()
+    }
+
+    // Iterates over allocated cells in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, W)> + '_ {
+        let mut sparse: Vec<_> = self.sparse.iter().map(|(&k, v)| (k, v.clone())).collect();
+        sparse.sort_by_key(|&(k, _)| k);
+        self.dense.iter().cloned().enumerate().chain(sparse)
+    }
+
+    // Maximum number of cells this memory may use.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // Number of cells currently allocated.
+    pub fn cells(&self) -> usize {
+        self.dense.len() + self.sparse.len()
+    }
+}
+
+impl<W> Index<usize> for Memory<W> {
+    type Output = W;
+
+    fn index(&self, address: usize) -> &W {
+        match self.dense.get(address) {
+            Some(x) => x,
+            None if self.sparse.is_empty() => &self.zero,
+            None => self.sparse.get(&address).unwrap_or(&self.zero),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grow() {
+        let mut mem: Memory = Memory::new(&[1, 2, 3], DEFAULT_LIMIT);
+        assert_eq!(mem[100], 0);
+        assert!(mem.set(100, 7));
+        assert_eq!(mem.range(0..4), vec![1, 2, 3, 0]);
+        assert_eq!(mem[100], 7);
+        assert_eq!(mem.cells(), 101);
+    }
+
+    #[test]
+    fn test_sparse() {
+        let mut mem: Memory = Memory::new(&[], DEFAULT_LIMIT);
+        assert!(mem.set(1 << 40, 5));
+        assert_eq!(mem[1 << 40], 5);
+        assert_eq!(mem[(1 << 40) + 1], 0);
+        assert_eq!(mem.cells(), 1);
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut mem: Memory = Memory::new(&[1, 2, 3], 10);
+        assert!(mem.set(9, 1));
+        assert!(!mem.set(10, 1));
+        assert!(!mem.set(1 << 40, 1));
+        assert!(mem.set(0, 5));
+        assert_eq!(mem[10], 0);
+
+        let mem: Memory = Memory::new(&[1, 2, 3], 2);
+        assert_eq!(mem.limit(), 3);
+    }
+}
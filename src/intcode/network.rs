@@ -0,0 +1,294 @@
+use super::{Budget, Budgeted, Computer, Error, State};
+
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+
+// Packets sent here go to the NAT instead of a machine.
+pub const NAT_ADDRESS: i64 = 255;
+
+// Consecutive rounds without any packets in flight before the network counts
+// as idle.
+const IDLE_ROUNDS: usize = 2;
+
+// Instructions a machine may execute in one turn, so a machine that never
+// reads input or halts can't stall the others.
+const TURN_INSTRUCTIONS: u64 = 100_000;
+
+// Traffic seen by the NAT, in order.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NatEvent {
+    // A packet sent to NAT_ADDRESS.
+    Received { x: i64, y: i64 },
+    // The NAT sent its last packet to address 0 because the network was idle.
+    Woke { x: i64, y: i64 },
+}
+
+// A fleet of machines exchanging (address, x, y) packets. Each machine is
+// given its address as its first input, outputs packets as three values, and
+// reads -1 when its queue is empty.
+//
+// Machines run one at a time in address order, so a run is deterministic. In
+// each round a machine runs until it has emptied its queue and been given -1
+// once, or until it has executed TURN_INSTRUCTIONS instructions. Packets are
+// delivered once the sending machine's turn ends; packets to addresses with
+// no machine are dropped.
+pub struct Network {
+    nodes: Vec<Node>,
+    // Last packet sent to the NAT.
+    nat: Option<(i64, i64)>,
+    log: Vec<NatEvent>,
+    idle_rounds: usize,
+}
+
+// Why a machine's turn ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Turn {
+    // It asked for input again after being given -1.
+    Blocked,
+    // It used up TURN_INSTRUCTIONS.
+    Exhausted,
+    Halted,
+}
+
+struct Node {
+    cpu: Computer,
+    queue: VecDeque<i64>,
+    // Output of a packet that is still being sent.
+    partial: Vec<i64>,
+    halted: bool,
+}
+
+impl Network {
+    // Creates n machines running program, with addresses 0 to n-1.
+    pub fn new(program: &[i64], n: usize) -> Network {
+        let nodes = (0..n)
+            .map(|address| Node {
+                cpu: Computer::new(program),
+                queue: VecDeque::from(vec![address as i64]),
+                partial: Vec::new(),
+                halted: false,
+            })
+            .collect();
+
+        Network {
+            nodes,
+            nat: None,
+            log: Vec::new(),
+            idle_rounds: 0,
+        }
+    }
+
+    // Queues a packet as if a machine had sent it.
+    pub fn send(&mut self, address: i64, x: i64, y: i64) {
+        if address == NAT_ADDRESS {
+            self.nat = Some((x, y));
+            self.log.push(NatEvent::Received { x, y });
+        } else if let Some(node) = usize::try_from(address)
+            .ok()
+            .and_then(|i| self.nodes.get_mut(i))
+        {
+            node.queue.extend([x, y]);
+        }
+    }
+
+    pub fn nat_log(&self) -> &[NatEvent] {
+        &self.log
+    }
+
+    // Runs one round, then wakes address 0 if the network has gone idle.
+    // Returns false once the network has stopped: every machine has halted,
+    // or it is idle and the NAT has nothing to send.
+    pub fn step(&mut self) -> Result<bool, Error> {
+        // The round is idle only if no packets were sent and every machine
+        // is waiting for one, rather than still computing or partway
+        // through sending.
+        let mut idle = self.nodes.iter().all(|n| n.queue.is_empty());
+
+        for i in 0..self.nodes.len() {
+            let (packets, turn) = self.nodes[i].run()?;
+            idle &= packets.is_empty() && self.nodes[i].partial.is_empty();
+            idle &= turn != Turn::Exhausted;
+            for [address, x, y] in packets {
+                self.send(address, x, y);
+            }
+        }
+
+        if self.nodes.iter().all(|n| n.halted) {
+            return Ok(false);
+        }
+
+        self.idle_rounds = if idle { self.idle_rounds + 1 } else { 0 };
+        if self.idle_rounds >= IDLE_ROUNDS {
+            let (x, y) = match self.nat {
+                Some(packet) => packet,
+                None => return Ok(false),
+            };
+            self.log.push(NatEvent::Woke { x, y });
+            self.send(0, x, y);
+            self.idle_rounds = 0;
+        }
+
+        Ok(true)
+    }
+
+    // Runs until stop returns true for the NAT log, which it is checked
+    // against before every round. Returns false if the network stopped first.
+    pub fn run<F: FnMut(&[NatEvent]) -> bool>(&mut self, mut stop: F) -> Result<bool, Error> {
+        loop {
+            if stop(&self.log) {
+                return Ok(true);
+            }
+            if !self.step()? {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+impl Node {
+    // Runs the machine for its turn. Returns the packets it sent and why
+    // the turn ended.
+    fn run(&mut self) -> Result<(Vec<[i64; 3]>, Turn), Error> {
+        let mut packets = Vec::new();
+        let mut polled = false;
+        let end = self.cpu.instruction_count() + TURN_INSTRUCTIONS;
+
+        let turn = loop {
+            if self.halted {
+                break Turn::Halted;
+            }
+            let budget = Budget {
+                instructions: Some(end.saturating_sub(self.cpu.instruction_count())),
+                deadline: None,
+            };
+            let state = match self.cpu.execute_with_budget(budget)? {
+                Budgeted::Stopped(state) => state,
+                Budgeted::Exhausted => break Turn::Exhausted,
+            };
+            match state {
+                State::InputRequested => match self.queue.pop_front() {
+                    Some(x) => self.cpu.input(x)?,
+                    None if polled => break Turn::Blocked,
+                    None => {
+                        self.cpu.input(-1)?;
+                        polled = true;
+                    }
+                },
+                State::Output(value) => {
+                    self.partial.push(value);
+                    if let [address, x, y] = self.partial[..] {
+                        packets.push([address, x, y]);
+                        self.partial.clear();
+                    }
+                }
+                State::Halted => self.halted = true,
+            }
+        };
+
+        Ok((packets, turn))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    // Each machine adds 1 to the y of every packet it receives and passes
+    // it to the next address. The last machine sends to the NAT.
+    const FORWARD: &str = "
+            n = 3
+            in [addr]
+        loop:
+            in [x]
+            eq [x], #-1, [t]
+            jt [t], #loop
+            in [y]
+            add [y], #1, [y]
+            add [addr], #1, [dest]
+            lt [dest], #n, [t]
+            jt [t], #send
+            add #255, #0, [dest]
+        send:
+            out [dest]
+            out [x]
+            out [y]
+            jt #1, #loop
+        addr: db 0
+        x: db 0
+        y: db 0
+        dest: db 0
+        t: db 0
+    ";
+
+    #[test]
+    fn test_nat() {
+        let program = asm::assemble(FORWARD).unwrap();
+        let mut network = Network::new(&program, 3);
+        network.send(0, 7, 0);
+
+        let woke = |log: &[NatEvent]| {
+            log.iter()
+                .filter(|e| matches!(e, NatEvent::Woke { .. }))
+                .count()
+        };
+        assert_eq!(network.run(|log| woke(log) == 2), Ok(true));
+        assert_eq!(
+            network.nat_log(),
+            [
+                NatEvent::Received { x: 7, y: 3 },
+                NatEvent::Woke { x: 7, y: 3 },
+                NatEvent::Received { x: 7, y: 6 },
+                NatEvent::Woke { x: 7, y: 6 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stops() {
+        // Nothing is ever sent, so the NAT has nothing to wake with.
+        let program = asm::assemble(FORWARD).unwrap();
+        let mut network = Network::new(&program, 3);
+        assert_eq!(network.run(|_| false), Ok(false));
+        assert!(network.nat_log().is_empty());
+
+        let mut network = Network::new(&[3, 0, 99], 2);
+        assert_eq!(network.run(|_| false), Ok(false));
+    }
+
+    #[test]
+    fn test_spinning() {
+        // Reads its address, then loops forever without reading again.
+        let mut network = Network::new(&[3, 5, 1105, 1, 2, 0], 2);
+        for _ in 0..5 {
+            assert_eq!(network.step(), Ok(true));
+        }
+    }
+
+    #[test]
+    fn test_long_turns() {
+        // Counts down for several turns' worth of instructions before
+        // sending to the NAT, so the network must not count as idle while
+        // it does.
+        let program = asm::assemble(
+            "
+                in [t]
+            loop:
+                add [n], #-1, [n]
+                jt [n], #loop
+                out #255
+                out #1
+                out #2
+            poll:
+                in [t]
+                jt #1, #poll
+            n:  db 150000
+            t:  db 0
+            ",
+        )
+        .unwrap();
+        let mut network = Network::new(&program, 2);
+        assert_eq!(network.run(|log| !log.is_empty()), Ok(true));
+        assert_eq!(network.nat_log()[0], NatEvent::Received { x: 1, y: 2 });
+    }
+}
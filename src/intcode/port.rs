@@ -0,0 +1,157 @@
+use super::{Computer, Error, State};
+
+use std::collections::VecDeque;
+use std::sync::mpsc;
+
+// Supplies input to a machine. Returns None when no input is available yet,
+// which blocks the machine until more arrives.
+pub trait Source {
+    fn recv(&mut self) -> Option<i64>;
+}
+
+// Receives a machine's output.
+pub trait Sink {
+    fn send(&mut self, value: i64);
+}
+
+impl Source for VecDeque<i64> {
+    fn recv(&mut self) -> Option<i64> {
+        self.pop_front()
+    }
+}
+
+impl Sink for VecDeque<i64> {
+    fn send(&mut self, value: i64) {
+        self.push_back(value);
+    }
+}
+
+impl Sink for Vec<i64> {
+    fn send(&mut self, value: i64) {
+        self.push(value);
+    }
+}
+
+// Never waits: an empty channel blocks the machine like an empty queue.
+impl Source for mpsc::Receiver<i64> {
+    fn recv(&mut self) -> Option<i64> {
+        self.try_recv().ok()
+    }
+}
+
+// Output sent after the receiver has gone away is dropped.
+impl Sink for mpsc::Sender<i64> {
+    fn send(&mut self, value: i64) {
+        let _ = mpsc::Sender::send(self, value);
+    }
+}
+
+// Feeds a machine from an iterator.
+pub struct IterSource<I>(pub I);
+
+impl<I: Iterator<Item = i64>> Source for IterSource<I> {
+    fn recv(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+impl Computer {
+    // Runs, reading input from input and writing output to output, until
+    // input has nothing left (State::InputRequested) or the machine halts.
+    pub fn run<I, O>(&mut self, input: &mut I, output: &mut O) -> Result<State, Error>
+    where
+        I: Source + ?Sized,
+        O: Sink + ?Sized,
+    {
+        loop {
+            match self.execute()? {
+                State::InputRequested => match input.recv() {
+                    Some(x) => self.input(x)?,
+                    None => return Ok(State::InputRequested),
+                },
+                State::Output(x) => output.send(x),
+                state => return Ok(state),
+            }
+        }
+    }
+}
+
+// A Computer wired to an input source and an output sink. By default both
+// are queues, so a driver pushes input, runs, and pops output.
+pub struct Machine<I = VecDeque<i64>, O = VecDeque<i64>> {
+    pub cpu: Computer,
+    pub input: I,
+    pub output: O,
+}
+
+impl Machine {
+    pub fn new(program: &[i64]) -> Machine {
+        Machine::with_io(Computer::new(program), VecDeque::new(), VecDeque::new())
+    }
+
+    pub fn push_input(&mut self, value: i64) {
+        self.input.push_back(value);
+    }
+
+    pub fn pop_output(&mut self) -> Option<i64> {
+        self.output.pop_front()
+    }
+}
+
+impl<I: Source, O: Sink> Machine<I, O> {
+    pub fn with_io(cpu: Computer, input: I, output: O) -> Machine<I, O> {
+        Machine { cpu, input, output }
+    }
+
+    // Runs until the machine blocks on input or halts.
+    pub fn run(&mut self) -> Result<State, Error> {
+        self.cpu.run(&mut self.input, &mut self.output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Outputs each input doubled until it reads 0, then halts.
+    const DOUBLER: [i64; 16] = [
+        3, 15, 1006, 15, 14, 102, 2, 15, 15, 4, 15, 1105, 1, 0, 99, 0,
+    ];
+
+    #[test]
+    fn test_machine() {
+        let mut m = Machine::new(&DOUBLER);
+        assert_eq!(m.run(), Ok(State::InputRequested));
+        m.push_input(3);
+        m.push_input(4);
+        assert_eq!(m.run(), Ok(State::InputRequested));
+        assert_eq!(m.pop_output(), Some(6));
+        assert_eq!(m.pop_output(), Some(8));
+        assert_eq!(m.pop_output(), None);
+        m.push_input(0);
+        assert_eq!(m.run(), Ok(State::Halted));
+        assert_eq!(m.run(), Ok(State::Halted));
+    }
+
+    #[test]
+    fn test_channels() {
+        let (input_tx, input_rx) = mpsc::channel();
+        let (output_tx, output_rx) = mpsc::channel();
+        let mut m = Machine::with_io(Computer::new(&DOUBLER), input_rx, output_tx);
+
+        input_tx.send(5).unwrap();
+        assert_eq!(m.run(), Ok(State::InputRequested));
+        assert_eq!(output_rx.try_recv(), Ok(10));
+        input_tx.send(0).unwrap();
+        assert_eq!(m.run(), Ok(State::Halted));
+    }
+
+    #[test]
+    fn test_iter_source() {
+        let mut cpu = Computer::new(&DOUBLER);
+        let mut output = Vec::new();
+        let state = cpu.run(&mut IterSource(1..4), &mut output);
+        assert_eq!(state, Ok(State::InputRequested));
+        assert_eq!(output, vec![2, 4, 6]);
+    }
+}
@@ -0,0 +1,146 @@
+use super::disasm::Instruction;
+use super::{Computer, Memory, Opcode, Word};
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+// Execution counts collected while profiling is enabled.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub total: u64,
+    // Executions of the instruction at each pc.
+    pub by_pc: HashMap<usize, u64>,
+    // Executions of each kind of instruction, keyed by mnemonic.
+    pub by_opcode: BTreeMap<&'static str, u64>,
+    // Loop headers, found as targets of taken backward jumps, and how many
+    // times each was jumped back to.
+    pub loops: HashMap<usize, u64>,
+}
+
+impl Profile {
+    // Renders the n hottest instructions, disassembled from ram, along with
+    // per-opcode counts and loop headers.
+    pub fn report(&self, ram: &Memory, n: usize) -> String {
+        let mut out = String::new();
+        let percent = |x: u64| 100.0 * x as f64 / self.total.max(1) as f64;
+
+        writeln!(out, "{} instructions executed", self.total).unwrap();
+        writeln!(out).unwrap();
+        writeln!(out, "hot spots:").unwrap();
+        for (pc, count) in ranked(&self.by_pc).into_iter().take(n) {
+            let inst = match Instruction::decode(pc, &ram.range(pc..pc + 4)) {
+                Ok(inst) => inst.to_string(),
+                Err(e) => format!("<{}>", e),
+            };
+            let marker = if self.loops.contains_key(&pc) {
+                "loop"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "{:>12} {:>6.2}% {:>6}: {:<4} {}",
+                count,
+                percent(count),
+                pc,
+                marker,
+                inst
+            )
+            .unwrap();
+        }
+
+        writeln!(out).unwrap();
+        writeln!(out, "by opcode:").unwrap();
+        let mut by_opcode: Vec<_> = self.by_opcode.iter().map(|(&k, &v)| (k, v)).collect();
+        by_opcode.sort_by_key(|&(k, v)| (Reverse(v), k));
+        for (mnemonic, count) in by_opcode {
+            writeln!(out, "{:>12} {:>6.2}% {}", count, percent(count), mnemonic).unwrap();
+        }
+
+        writeln!(out).unwrap();
+        writeln!(out, "loop headers (backward jumps taken):").unwrap();
+        for (pc, count) in ranked(&self.loops).into_iter().take(n) {
+            writeln!(out, "{:>12} {:>6}", count, pc).unwrap();
+        }
+
+        out
+    }
+}
+
+// Sorts counts by descending count, then by ascending address.
+fn ranked(counts: &HashMap<usize, u64>) -> Vec<(usize, u64)> {
+    let mut ret: Vec<_> = counts.iter().map(|(&k, &v)| (k, v)).collect();
+    ret.sort_by_key(|&(k, v)| (Reverse(v), k));
+    ret
+}
+
+impl<W: Word> Computer<W> {
+    // Starts counting executed instructions. Clears any existing profile.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    // Stops profiling and returns the collected profile.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    // Counts an instruction that just executed from pc.
+    pub(super) fn count(&mut self, pc: usize, opcode: Opcode) {
+        let new_pc = self.pc;
+        if let Some(profile) = self.profile.as_mut() {
+            profile.total += 1;
+            *profile.by_pc.entry(pc).or_default() += 1;
+            *profile.by_opcode.entry(opcode.mnemonic()).or_default() += 1;
+
+            let jump = matches!(opcode, Opcode::JumpIfTrue(..) | Opcode::JumpIfFalse(..));
+            if jump && new_pc <= pc {
+                *profile.loops.entry(new_pc).or_default() += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::intcode::{Computer, State};
+
+    #[test]
+    fn test_profile() {
+        // Counts [9] down from 3, then halts.
+        let mut cpu = Computer::new(&[1001, 9, -1, 9, 1005, 9, 0, 99, 0, 3]);
+        cpu.enable_profiling();
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+
+        let profile = cpu.take_profile().unwrap();
+        assert_eq!(profile.total, 7);
+        assert_eq!(profile.by_pc[&0], 3);
+        assert_eq!(profile.by_pc[&4], 3);
+        assert_eq!(profile.by_pc[&7], 1);
+        assert_eq!(profile.by_opcode["add"], 3);
+        assert_eq!(profile.loops[&0], 2);
+        assert_eq!(profile.loops.len(), 1);
+
+        let expected = "7 instructions executed
+
+hot spots:
+           3  42.86%      0: loop add [9], #-1, [9]
+           3  42.86%      4:      jt [9], #0
+           1  14.29%      7:      hlt
+
+by opcode:
+           3  42.86% add
+           3  42.86% jt
+           1  14.29% hlt
+
+loop headers (backward jumps taken):
+           2      0
+";
+        assert_eq!(profile.report(&cpu.ram, 10), expected);
+    }
+}
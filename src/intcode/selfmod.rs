@@ -0,0 +1,169 @@
+use super::{disasm, word, Computer, Opcode, Word};
+
+use std::collections::HashSet;
+use std::fmt;
+
+// A write into a cell holding code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeWrite<W = i64> {
+    // Address of the instruction that wrote.
+    pub pc: usize,
+    pub address: usize,
+    pub old: W,
+    pub new: W,
+    // Whether the cell had already been executed. If not, it was predicted
+    // to be code by disassembling ram when watching started.
+    pub executed: bool,
+}
+
+// Formats the write as a single line, for example
+// "pc 2 wrote [1]: 12 -> 13 (executed)".
+impl<W: fmt::Display> fmt::Display for CodeWrite<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.executed {
+            "executed"
+        } else {
+            "predicted"
+        };
+        write!(
+            f,
+            "pc {} wrote [{}]: {} -> {} ({})",
+            self.pc, self.address, self.old, self.new, kind
+        )
+    }
+}
+
+pub(super) struct CodeWatch<W> {
+    // Every cell that has been part of an executed instruction.
+    executed: HashSet<usize>,
+    // Cells disassembly found as code when watching started.
+    predicted: HashSet<usize>,
+    writes: Vec<CodeWrite<W>>,
+}
+
+impl<W: Word> Computer<W> {
+    // Starts recording writes into code, which a program that is safe to
+    // compile or cache never makes. Cells count as code once they are
+    // executed as part of an instruction, or if disassembling ram now finds
+    // them as code. Clears any existing record.
+    pub fn enable_code_watch(&mut self) {
+        let program: Vec<i64> = self
+            .ram
+            .iter()
+            .enumerate()
+            .take_while(|&(i, (address, _))| i == address)
+            .map(|(_, (_, x))| word::saturate(&x))
+            .collect();
+
+        let mut predicted = HashSet::new();
+        for line in disasm::disassemble(&program) {
+            if let disasm::Line::Code(inst) = line {
+                predicted.extend(inst.address..inst.address + inst.size());
+            }
+        }
+
+        self.code_watch = Some(CodeWatch {
+            executed: HashSet::new(),
+            predicted,
+            writes: Vec::new(),
+        });
+    }
+
+    // Writes into code seen so far, in order. Empty if not watching.
+    pub fn code_writes(&self) -> &[CodeWrite<W>] {
+        match self.code_watch.as_ref() {
+            Some(watch) => &watch.writes,
+            None => &[],
+        }
+    }
+
+    // Stops watching and returns the writes into code that were seen.
+    pub fn take_code_writes(&mut self) -> Option<Vec<CodeWrite<W>>> {
+        self.code_watch.take().map(|watch| watch.writes)
+    }
+
+    // Checks an instruction that just executed from pc for a write into
+    // code. Must run before record, which consumes overwritten.
+    pub(super) fn watch_code(&mut self, pc: usize, opcode: Opcode) {
+        let watch = match self.code_watch.as_mut() {
+            Some(x) => x,
+            None => return,
+        };
+
+        // An instruction that overwrites itself counts as executed code.
+        watch.executed.extend(pc..pc + opcode.size());
+
+        let (address, new) = match &self.last_write {
+            Some(x) => x.clone(),
+            None => return,
+        };
+        let executed = watch.executed.contains(&address);
+        if !executed && !watch.predicted.contains(&address) {
+            return;
+        }
+
+        let old = match &self.overwritten {
+            Some((a, old)) if *a == address => old.clone(),
+            _ => unreachable!("overwritten is kept while watching code"),
+        };
+        watch.writes.push(CodeWrite {
+            pc,
+            address,
+            old,
+            new,
+            executed,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::State;
+
+    #[test]
+    fn test_executed() {
+        // Outputs [12], then overwrites the operand of its out instruction
+        // and jumps back to it.
+        let mut cpu = Computer::new(&[4, 12, 1101, 13, 0, 1, 1105, 1, 0, 0, 0, 0, 7, 99]);
+        cpu.enable_code_watch();
+        assert_eq!(cpu.execute(), Ok(State::Output(7)));
+        assert!(cpu.code_writes().is_empty());
+        assert_eq!(cpu.execute(), Ok(State::Output(99)));
+
+        let writes = cpu.take_code_writes().unwrap();
+        assert_eq!(
+            writes,
+            vec![CodeWrite {
+                pc: 2,
+                address: 1,
+                old: 12,
+                new: 13,
+                executed: true,
+            }]
+        );
+        assert_eq!(writes[0].to_string(), "pc 2 wrote [1]: 12 -> 13 (executed)");
+    }
+
+    #[test]
+    fn test_predicted() {
+        // Patches the operand of the second out before reaching it.
+        let mut cpu = Computer::new(&[1101, 2, 0, 7, 104, 5, 104, 1, 99]);
+        cpu.enable_code_watch();
+        assert_eq!(cpu.execute(), Ok(State::Output(5)));
+        assert_eq!(cpu.execute(), Ok(State::Output(2)));
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+        assert_eq!(
+            cpu.code_writes()[0].to_string(),
+            "pc 0 wrote [7]: 1 -> 2 (predicted)"
+        );
+    }
+
+    #[test]
+    fn test_data_writes() {
+        let mut cpu = Computer::new(&[1101, 2, 3, 5, 99, 0]);
+        cpu.enable_code_watch();
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+        assert!(cpu.code_writes().is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use super::{Computer, Word};
+
+use std::error;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const HEADER: &str = "intcode-state 2";
+
+// Machine state is saved as text:
+//
+//     intcode-state 2
+//     pc 12
+//     rb 0
+//     instructions 4
+//     limit 16777216
+//     ram 0 1,9,10,3,2,3,11,0,99,30,40,50
+//     ram 1099511627776 5
+//
+// Each ram line holds a run of consecutive cells starting at an address.
+impl<W: Word> Computer<W>
+where
+    <W as FromStr>::Err: error::Error + 'static,
+{
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(fs::File::create(path)?);
+        self.write_state(&mut out)?;
+        out.flush()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Computer<W>, Box<dyn error::Error>> {
+        Computer::read_state(BufReader::new(fs::File::open(path)?))
+    }
+
+    pub fn write_state<O: Write>(&self, out: &mut O) -> io::Result<()> {
+        writeln!(out, "{}", HEADER)?;
+        writeln!(out, "pc {}", self.pc)?;
+        writeln!(out, "rb {}", self.relative_base)?;
+        writeln!(out, "instructions {}", self.instructions)?;
+        writeln!(out, "limit {}", self.ram.limit())?;
+
+        let mut run: Vec<W> = Vec::new();
+        let mut start = 0;
+        for (address, value) in self.ram.iter() {
+            if address != start + run.len() {
+                write_run(out, start, &run)?;
+                run.clear();
+                start = address;
+            }
+            run.push(value);
+        }
+        write_run(out, start, &run)
+    }
+
+    pub fn read_state<R: BufRead>(input: R) -> Result<Computer<W>, Box<dyn error::Error>> {
+        let mut lines = input.lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err("not an intcode state file".into());
+        }
+
+        let mut field = |name: &str| -> Result<String, Box<dyn error::Error>> {
+            let line = lines.next().transpose()?.unwrap_or_default();
+            match line.strip_prefix(name).and_then(|x| x.strip_prefix(' ')) {
+                Some(x) => Ok(x.to_string()),
+                None => Err(format!("expected {} line", name).into()),
+            }
+        };
+        let pc = field("pc")?.parse()?;
+        let relative_base = field("rb")?.parse()?;
+        let instructions = field("instructions")?.parse()?;
+        let limit = field("limit")?.parse()?;
+
+        let mut cpu = Computer::from_words(&[], limit);
+        cpu.pc = pc;
+        cpu.relative_base = relative_base;
+        cpu.instructions = instructions;
+
+        for line in lines {
+            let line = line?;
+            let run = match line.strip_prefix("ram ") {
+                Some(x) => x,
+                None if line.is_empty() => continue,
+                None => return Err("expected ram line".into()),
+            };
+
+            let mut parts = run.splitn(2, ' ');
+            let start: usize = parts.next().unwrap_or_default().parse()?;
+            for (i, x) in parts.next().unwrap_or_default().split(',').enumerate() {
+                let address = start.checked_add(i).ok_or("bad ram line")?;
+                if !cpu.ram.set(address, x.parse()?) {
+                    return Err("state exceeds its memory limit".into());
+                }
+            }
+        }
+
+        Ok(cpu)
+    }
+}
+
+fn write_run<O: Write, W: Word>(out: &mut O, start: usize, run: &[W]) -> io::Result<()> {
+    if run.is_empty() {
+        return Ok(());
+    }
+    let cells: Vec<_> = run.iter().map(|x| x.to_string()).collect();
+    writeln!(out, "ram {} {}", start, cells.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::State;
+
+    // Outputs its input plus 1.
+    const PROGRAM: [i64; 11] = [3, 9, 1001, 9, 1, 10, 4, 10, 99, 0, 0];
+
+    #[test]
+    fn test_clone() {
+        let mut cpu = Computer::new(&[3, 9, 4, 9, 3, 9, 4, 9, 99, 0]);
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        cpu.input(1).unwrap();
+        assert_eq!(cpu.execute(), Ok(State::Output(1)));
+
+        let mut fork = cpu.clone();
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        assert_eq!(fork.execute(), Ok(State::InputRequested));
+        cpu.input(2).unwrap();
+        fork.input(3).unwrap();
+        assert_eq!(cpu.execute(), Ok(State::Output(2)));
+        assert_eq!(fork.execute(), Ok(State::Output(3)));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut cpu = Computer::with_memory_limit(&PROGRAM, 1000);
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        cpu.input(41).unwrap();
+        assert!(cpu.ram.set(1 << 40, 5));
+
+        let mut buf = Vec::new();
+        cpu.write_state(&mut buf).unwrap();
+        let text = String::from_utf8(buf.clone()).unwrap();
+        assert_eq!(
+            text,
+            "intcode-state 2\npc 2\nrb 0\ninstructions 1\nlimit 1000\n\
+             ram 0 3,9,1001,9,1,10,4,10,99,41,0\n\
+             ram 1099511627776 5\n"
+        );
+
+        let mut restored: Computer = Computer::read_state(&buf[..]).unwrap();
+        assert_eq!(restored.ram.limit(), 1000);
+        assert_eq!(restored.ram[1 << 40], 5);
+        assert_eq!(restored.instruction_count(), 1);
+        assert_eq!(restored.execute(), Ok(State::Output(42)));
+    }
+
+    #[test]
+    fn test_program_over_limit() {
+        let cpu = Computer::with_memory_limit(&[99, 0, 0], 2);
+        let mut buf = Vec::new();
+        cpu.write_state(&mut buf).unwrap();
+        let restored: Computer = Computer::read_state(&buf[..]).unwrap();
+        assert_eq!(restored.ram.range(0..3), vec![99, 0, 0]);
+    }
+
+    #[test]
+    fn test_read_errors() {
+        let err = |text: &str| {
+            Computer::<i64>::read_state(text.as_bytes())
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(err("1,2,3"), "not an intcode state file");
+        assert_eq!(err("intcode-state 2\nrb 0"), "expected pc line");
+        assert_eq!(
+            err("intcode-state 2\npc 0\nrb 0\nlimit 2"),
+            "expected instructions line"
+        );
+        assert_eq!(
+            err("intcode-state 2\npc 0\nrb 0\ninstructions 0\nlimit 2\nram 0 1,2,3"),
+            "state exceeds its memory limit"
+        );
+        assert_eq!(
+            err("intcode-state 2\npc 0\nrb 0\ninstructions 0\nlimit 2\nram 18446744073709551615 1,2"),
+            "bad ram line"
+        );
+    }
+}
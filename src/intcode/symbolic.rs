@@ -0,0 +1,634 @@
+use super::{parse_opcode, Memory, Opcode, ParameterMode, State, DEFAULT_LIMIT};
+use crate::intcode;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error;
+use std::fmt;
+use std::ops::Range;
+
+// A value built from constants and symbols by the operations intcode has.
+// Sums and products are kept as a polynomial, so for example (x + 1) * 2
+// and 2 * x + 2 are the same Expr.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Expr {
+    // Coefficient of each product of atoms, with the atoms sorted. The empty
+    // product is the constant term. No coefficient is 0.
+    terms: BTreeMap<Vec<Atom>, i64>,
+}
+
+// A value that can't be broken down further.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Atom {
+    Symbol(String),
+    // The cell at a symbolic address, as it was when read.
+    Load(Expr),
+    LessThan(Expr, Expr),
+    Equals(Expr, Expr),
+}
+
+impl Expr {
+    pub fn constant(x: i64) -> Expr {
+        let mut terms = BTreeMap::new();
+        if x != 0 {
+            terms.insert(Vec::new(), x);
+        }
+        Expr { terms }
+    }
+
+    pub fn symbol(name: &str) -> Expr {
+        Expr::atom(Atom::Symbol(name.to_string()))
+    }
+
+    fn atom(atom: Atom) -> Expr {
+        let mut terms = BTreeMap::new();
+        terms.insert(vec![atom], 1);
+        Expr { terms }
+    }
+
+    // Returns the value if it doesn't depend on any symbol.
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.terms.iter().next() {
+            None => Some(0),
+            Some((atoms, &x)) if atoms.is_empty() && self.terms.len() == 1 => Some(x),
+            _ => None,
+        }
+    }
+
+    // 1 if self < other, otherwise 0. Stays symbolic unless the difference
+    // between the two is constant.
+    pub fn less_than(&self, other: &Expr) -> Expr {
+        match self.difference(other) {
+            Some(x) => Expr::constant((x < 0) as i64),
+            None => Expr::atom(Atom::LessThan(self.clone(), other.clone())),
+        }
+    }
+
+    // 1 if self == other, otherwise 0.
+    pub fn equals(&self, other: &Expr) -> Expr {
+        match self.difference(other) {
+            Some(x) => Expr::constant((x == 0) as i64),
+            None => Expr::atom(Atom::Equals(self.clone(), other.clone())),
+        }
+    }
+
+    // self - other, if that is constant.
+    fn difference(&self, other: &Expr) -> Option<i64> {
+        let negated = other.checked_mul(&Expr::constant(-1))?;
+        self.checked_add(&negated)?.as_constant()
+    }
+
+    // Evaluates the expression given a value for each symbol in it. Returns
+    // None if a symbol is missing, the arithmetic overflows, or the value
+    // depends on a load from a symbolic address.
+    pub fn eval(&self, values: &HashMap<String, i64>) -> Option<i64> {
+        let mut total: i64 = 0;
+        for (atoms, &coefficient) in &self.terms {
+            let mut term = coefficient;
+            for atom in atoms {
+                let x = match atom {
+                    Atom::Symbol(name) => *values.get(name)?,
+                    Atom::Load(_) => return None,
+                    Atom::LessThan(a, b) => (a.eval(values)? < b.eval(values)?) as i64,
+                    Atom::Equals(a, b) => (a.eval(values)? == b.eval(values)?) as i64,
+                };
+                term = term.checked_mul(x)?;
+            }
+            total = total.checked_add(term)?;
+        }
+        Some(total)
+    }
+
+    // Splits the expression into a constant and a coefficient for each
+    // symbol, if it is a sum of those alone.
+    fn linear(&self) -> Option<(i64, HashMap<&str, i64>)> {
+        let mut constant = 0;
+        let mut coefficients = HashMap::new();
+        for (atoms, &coefficient) in &self.terms {
+            match &atoms[..] {
+                [] => constant = coefficient,
+                [Atom::Symbol(name)] => {
+                    coefficients.insert(name.as_str(), coefficient);
+                }
+                _ => return None,
+            }
+        }
+        Some((constant, coefficients))
+    }
+
+    // self + other, or None if a coefficient overflows.
+    pub fn checked_add(&self, other: &Expr) -> Option<Expr> {
+        let mut terms = self.terms.clone();
+        for (atoms, &coefficient) in &other.terms {
+            let sum = i64::checked_add(*terms.get(atoms).unwrap_or(&0), coefficient)?;
+            if sum == 0 {
+                terms.remove(atoms);
+            } else {
+                terms.insert(atoms.clone(), sum);
+            }
+        }
+        Some(Expr { terms })
+    }
+
+    // self * other, or None if a coefficient overflows.
+    pub fn checked_mul(&self, other: &Expr) -> Option<Expr> {
+        let mut product = Expr::default();
+        for (a, &x) in &self.terms {
+            for (b, &y) in &other.terms {
+                let mut atoms: Vec<Atom> = a.iter().chain(b).cloned().collect();
+                atoms.sort();
+                let mut term = BTreeMap::new();
+                term.insert(atoms, x.checked_mul(y)?);
+                product = product.checked_add(&Expr { terms: term })?;
+            }
+        }
+        Some(product)
+    }
+}
+
+// Formats the expression with the constant term last, for example
+// "230400*noun + verb + 337042".
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+
+        // Highest degree first, which leaves the constant until last.
+        let mut terms: Vec<_> = self.terms.iter().collect();
+        terms.sort_by_key(|(atoms, _)| Reverse(atoms.len()));
+        for (i, (atoms, &coefficient)) in terms.into_iter().enumerate() {
+            let magnitude = coefficient.unsigned_abs();
+            match (i, coefficient < 0) {
+                (0, true) => write!(f, "-")?,
+                (0, false) => (),
+                (_, true) => write!(f, " - ")?,
+                (_, false) => write!(f, " + ")?,
+            }
+
+            let mut factors: Vec<String> = atoms.iter().map(|x| x.to_string()).collect();
+            if magnitude != 1 || factors.is_empty() {
+                factors.insert(0, magnitude.to_string());
+            }
+            write!(f, "{}", factors.join("*"))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Atom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Atom::Symbol(name) => write!(f, "{}", name),
+            Atom::Load(address) => write!(f, "mem[{}]", address),
+            Atom::LessThan(a, b) => write!(f, "({} < {})", a, b),
+            Atom::Equals(a, b) => write!(f, "({} == {})", a, b),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    // An error the program would also hit on a Computer.
+    Intcode(intcode::Error),
+    // The instruction at pc has an opcode, jump or write address that
+    // depends on a symbol.
+    Symbolic { pc: usize },
+}
+
+impl From<intcode::Error> for Error {
+    fn from(e: intcode::Error) -> Error {
+        Error::Intcode(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Intcode(e) => write!(f, "{}", e),
+            Error::Symbolic { pc } => write!(f, "value depends on a symbol at pc {}", pc),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+// Runs a program whose memory and input may hold symbols. Jumps, writes
+// and the relative base must not depend on a symbol, so execution follows
+// a single path; reads from a symbolic address give a symbolic load.
+pub struct Symbolic {
+    pub ram: Memory<Expr>,
+    pc: usize,
+    relative_base: i64,
+    input: VecDeque<Expr>,
+}
+
+impl Symbolic {
+    pub fn new(program: &[i64]) -> Symbolic {
+        let cells: Vec<Expr> = program.iter().map(|&x| Expr::constant(x)).collect();
+        Symbolic {
+            ram: Memory::with_zero(&cells, DEFAULT_LIMIT, Expr::constant(0)),
+            pc: 0,
+            relative_base: 0,
+            input: VecDeque::new(),
+        }
+    }
+
+    // Replaces the cell at address with a symbol called name. Returns false
+    // if address is beyond the memory limit.
+    #[must_use]
+    pub fn set_symbol(&mut self, address: usize, name: &str) -> bool {
+        self.ram.set(address, Expr::symbol(name))
+    }
+
+    // Queues a value for the program to read.
+    pub fn push_input(&mut self, value: Expr) {
+        self.input.push_back(value);
+    }
+
+    // Runs until the program halts, outputs a value, or needs input that
+    // hasn't been queued. Fails with Error::Symbolic at an instruction
+    // whose control flow or write address depends on a symbol.
+    pub fn execute(&mut self) -> Result<State<Expr>, Error> {
+        loop {
+            let pc = self.pc;
+            let opcode = match self.ram[pc].as_constant() {
+                Some(x) => parse_opcode(pc, x)?,
+                None => return Err(Error::Symbolic { pc }),
+            };
+            let mut next = pc + opcode.size();
+
+            match opcode {
+                Opcode::Add(m1, m2, m3) | Opcode::Mul(m1, m2, m3) => {
+                    let (a, b) = (self.read(m1, 1)?, self.read(m2, 2)?);
+                    let value = if let Opcode::Add(..) = opcode {
+                        a.checked_add(&b)
+                    } else {
+                        a.checked_mul(&b)
+                    };
+                    self.write(m3, 3, value.ok_or(intcode::Error::Overflow { pc })?)?;
+                }
+                Opcode::LessThan(m1, m2, m3) => {
+                    let value = self.read(m1, 1)?.less_than(&self.read(m2, 2)?);
+                    self.write(m3, 3, value)?;
+                }
+                Opcode::Equals(m1, m2, m3) => {
+                    let value = self.read(m1, 1)?.equals(&self.read(m2, 2)?);
+                    self.write(m3, 3, value)?;
+                }
+                Opcode::Input(m1) => match self.input.pop_front() {
+                    Some(value) => self.write(m1, 1, value)?,
+                    None => return Ok(State::InputRequested),
+                },
+                Opcode::Output(m1) => {
+                    let value = self.read(m1, 1)?;
+                    self.pc = next;
+                    return Ok(State::Output(value));
+                }
+                Opcode::JumpIfTrue(m1, m2) | Opcode::JumpIfFalse(m1, m2) => {
+                    let cond = self.concrete(m1, 1)? != 0;
+                    if cond == matches!(opcode, Opcode::JumpIfTrue(..)) {
+                        next = self.address(self.concrete(m2, 2)?)?;
+                    }
+                }
+                Opcode::AdjustRelativeBase(m1) => {
+                    let offset = self.concrete(m1, 1)?;
+                    self.relative_base = self
+                        .relative_base
+                        .checked_add(offset)
+                        .ok_or(intcode::Error::Overflow { pc })?;
+                }
+                Opcode::Halt => return Ok(State::Halted),
+                Opcode::Custom(..) => unreachable!("parse_opcode has no custom opcodes"),
+            }
+            self.pc = next;
+        }
+    }
+
+    fn read(&self, mode: ParameterMode, offset: usize) -> Result<Expr, Error> {
+        let cell = self.ram.get(self.pc + offset);
+        let address = match mode {
+            ParameterMode::Immediate => return Ok(cell),
+            ParameterMode::Position => cell,
+            ParameterMode::Relative => Expr::constant(self.relative_base)
+                .checked_add(&cell)
+                .ok_or(intcode::Error::Overflow { pc: self.pc })?,
+        };
+        match address.as_constant() {
+            Some(x) => Ok(self.ram.get(self.address(x)?)),
+            None => Ok(Expr::atom(Atom::Load(address))),
+        }
+    }
+
+    // Reads an operand that must not depend on a symbol.
+    fn concrete(&self, mode: ParameterMode, offset: usize) -> Result<i64, Error> {
+        self.read(mode, offset)?
+            .as_constant()
+            .ok_or(Error::Symbolic { pc: self.pc })
+    }
+
+    fn write(&mut self, mode: ParameterMode, offset: usize, value: Expr) -> Result<(), Error> {
+        let pc = self.pc;
+        let cell = match self.ram[pc + offset].as_constant() {
+            Some(x) => x,
+            None => return Err(Error::Symbolic { pc }),
+        };
+        let address = match mode {
+            ParameterMode::Position => cell,
+            ParameterMode::Relative => self
+                .relative_base
+                .checked_add(cell)
+                .ok_or(intcode::Error::Overflow { pc })?,
+            ParameterMode::Immediate => return Err(intcode::Error::ImmediateWrite { pc }.into()),
+        };
+
+        let address = self.address(address)?;
+        if self.ram.set(address, value) {
+            Ok(())
+        } else {
+            Err(intcode::Error::AddressOutOfRange { pc, address }.into())
+        }
+    }
+
+    fn address(&self, x: i64) -> Result<usize, Error> {
+        if x < 0 {
+            Err(intcode::Error::NegativeAddress {
+                pc: self.pc,
+                address: x,
+            }
+            .into())
+        } else {
+            Ok(x as usize)
+        }
+    }
+}
+
+// Finds values for the symbols named in bounds, each within its range, for
+// which expr equals target. expr must be a linear combination of those
+// symbols plus a constant. Values are returned in the order of bounds; if
+// there are several solutions, the one with the smallest first value is
+// returned.
+//
+// The last two symbols are solved for directly, as a linear Diophantine
+// equation, so for two symbols this takes constant time.
+pub fn solve(expr: &Expr, target: i64, bounds: &[(&str, Range<i64>)]) -> Option<Vec<i64>> {
+    let (constant, mut by_name) = expr.linear()?;
+    let coefficients: Vec<i128> = bounds
+        .iter()
+        .map(|(name, _)| by_name.remove(name).unwrap_or(0).into())
+        .collect();
+    if !by_name.is_empty() {
+        return None;
+    }
+
+    // Ranges are inclusive from here on.
+    let ranges: Vec<(i128, i128)> = bounds
+        .iter()
+        .map(|(_, r)| (r.start.into(), i128::from(r.end) - 1))
+        .collect();
+    let rhs = i128::from(target) - i128::from(constant);
+    let values = solve_linear(&coefficients, &ranges, rhs)?;
+    Some(values.into_iter().map(|x| x as i64).collect())
+}
+
+// Solves sum(coefficients[i] * x[i]) == rhs with each x[i] in ranges[i].
+fn solve_linear(coefficients: &[i128], ranges: &[(i128, i128)], rhs: i128) -> Option<Vec<i128>> {
+    if ranges.iter().any(|&(lo, hi)| lo > hi) {
+        return None;
+    }
+
+    match (coefficients, ranges) {
+        ([], []) if rhs == 0 => Some(Vec::new()),
+        ([], []) => None,
+        ([a], [(lo, hi)]) => {
+            let x = match *a {
+                0 if rhs == 0 => *lo,
+                0 => return None,
+                a if rhs % a == 0 => rhs / a,
+                _ => return None,
+            };
+            Some(vec![x]).filter(|_| x >= *lo && x <= *hi)
+        }
+        ([a, b], [xs, ys]) => solve_pair(*a, *b, *xs, *ys, rhs).map(|(x, y)| vec![x, y]),
+        _ => {
+            let (lo, hi) = ranges[0];
+            (lo..=hi).find_map(|x| {
+                let rest = rhs.checked_sub(coefficients[0].checked_mul(x)?)?;
+                let mut values = solve_linear(&coefficients[1..], &ranges[1..], rest)?;
+                values.insert(0, x);
+                Some(values)
+            })
+        }
+    }
+}
+
+// Solves a * x + b * y == rhs for x in xs and y in ys, preferring the
+// smallest x.
+fn solve_pair(
+    a: i128,
+    b: i128,
+    xs: (i128, i128),
+    ys: (i128, i128),
+    rhs: i128,
+) -> Option<(i128, i128)> {
+    // If x or y doesn't matter, take it as small as it can be.
+    if a == 0 {
+        let y = solve_linear(&[b], &[ys], rhs)?[0];
+        return Some((xs.0, y));
+    }
+    if b == 0 {
+        let x = solve_linear(&[a], &[xs], rhs)?[0];
+        return Some((x, ys.0));
+    }
+
+    // Every solution is (x0 + k * dx, y0 - k * dy) for some integer k.
+    let (g, p, q) = extended_gcd(a, b);
+    if rhs % g != 0 {
+        return None;
+    }
+    let (x0, y0) = (p * (rhs / g), q * (rhs / g));
+    let (dx, dy) = (b / g, a / g);
+
+    let (k1, k2) = steps_within(x0, dx, xs);
+    let (k3, k4) = steps_within(y0, -dy, ys);
+    let (lo, hi) = (k1.max(k3), k2.min(k4));
+    if lo > hi {
+        return None;
+    }
+    let k = if dx > 0 { lo } else { hi };
+    Some((x0 + k * dx, y0 - k * dy))
+}
+
+// The range of k for which start + k * step lies within range. step must
+// not be 0.
+fn steps_within(start: i128, step: i128, (lo, hi): (i128, i128)) -> (i128, i128) {
+    if step > 0 {
+        (div_ceil(lo - start, step), div_floor(hi - start, step))
+    } else {
+        (div_ceil(hi - start, step), div_floor(lo - start, step))
+    }
+}
+
+fn div_floor(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if (a % b != 0) && ((a < 0) != (b < 0)) {
+        q - 1
+    } else {
+        q
+    }
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+    -div_floor(-a, b)
+}
+
+// Returns (g, p, q) with a * p + b * q == g, where g is the greatest common
+// divisor of a and b and is positive.
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    let (mut r0, mut r1) = (a, b);
+    let (mut p0, mut p1) = (1, 0);
+    let (mut q0, mut q1) = (0, 1);
+    while r1 != 0 {
+        let t = r0 / r1;
+        (r0, r1) = (r1, r0 - t * r1);
+        (p0, p1) = (p1, p0 - t * p1);
+        (q0, q1) = (q1, q0 - t * q1);
+    }
+    if r0 < 0 {
+        (-r0, -p0, -q0)
+    } else {
+        (r0, p0, q0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::asm;
+
+    fn x() -> Expr {
+        Expr::symbol("x")
+    }
+
+    #[test]
+    fn test_expr() {
+        let a = x().checked_mul(&Expr::constant(2)).unwrap();
+        let a = a.checked_add(&Expr::constant(3)).unwrap();
+        let b = x().checked_add(&Expr::constant(-1)).unwrap();
+        let product = a.checked_mul(&b).unwrap();
+        assert_eq!(product.to_string(), "2*x*x + x - 3");
+
+        let values = [("x".to_string(), 4)].iter().cloned().collect();
+        assert_eq!(product.eval(&values), Some(33));
+        assert_eq!(
+            a.less_than(&a.checked_add(&Expr::constant(1)).unwrap()),
+            Expr::constant(1)
+        );
+        assert_eq!(a.equals(&b).to_string(), "(2*x + 3 == x - 1)");
+        assert_eq!(Expr::constant(-5).to_string(), "-5");
+    }
+
+    #[test]
+    fn test_execute() {
+        // Outputs 3 * input + [a], then jumps on the result.
+        let program = asm::assemble(
+            "
+                in [t]
+                mul [t], #3, [t]
+                add [t], [a], [t]
+                out [t]
+                jt [t], #0
+                hlt
+            a:  db 0
+            t:  db 0
+            ",
+        )
+        .unwrap();
+        let mut cpu = Symbolic::new(&program);
+        assert!(cpu.set_symbol(16, "a"));
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        cpu.push_input(Expr::symbol("in"));
+        let expected = "a + 3*in";
+        match cpu.execute() {
+            Ok(State::Output(x)) => assert_eq!(x.to_string(), expected),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(cpu.execute(), Err(Error::Symbolic { pc: 12 }));
+    }
+
+    #[test]
+    fn test_symbolic_address() {
+        // Reading through a symbolic address is fine, writing isn't.
+        let mut cpu = Symbolic::new(&[1001, 0, 1, 5, 99, 0]);
+        assert!(cpu.set_symbol(1, "p"));
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+        assert_eq!(cpu.ram.get(5).to_string(), "mem[p] + 1");
+
+        let mut cpu = Symbolic::new(&[1101, 1, 1, 0, 99]);
+        assert!(cpu.set_symbol(3, "p"));
+        assert_eq!(cpu.execute(), Err(Error::Symbolic { pc: 0 }));
+
+        let mut cpu = Symbolic::new(&[1, -1, 0, 0, 99]);
+        let err = intcode::Error::NegativeAddress { pc: 0, address: -1 };
+        assert_eq!(cpu.execute(), Err(Error::Intcode(err)));
+    }
+
+    #[test]
+    fn test_solve() {
+        let expr = x()
+            .checked_mul(&Expr::constant(230400))
+            .unwrap()
+            .checked_add(&Expr::symbol("y"))
+            .unwrap()
+            .checked_add(&Expr::constant(337042))
+            .unwrap();
+        let bounds = [("x", 0..100), ("y", 0..100)];
+        assert_eq!(solve(&expr, 19690720, &bounds), Some(vec![84, 78]));
+        assert_eq!(solve(&expr, 19690720 + 100, &bounds), None);
+        assert_eq!(solve(&expr, 0, &[("x", 0..100)]), None);
+
+        let square = x().checked_mul(&x()).unwrap();
+        assert_eq!(solve(&square, 4, &[("x", 0..10)]), None);
+    }
+
+    #[test]
+    fn test_solve_matches_search() {
+        // Compares against trying every value, for a mix of signs and
+        // common factors.
+        let names = ["x", "y", "z"];
+        for coefficients in &[[6, 4, 0], [-3, 5, 1], [0, 2, 0], [7, -7, 2], [2, 0, 0]] {
+            let n = if coefficients[2] == 0 { 2 } else { 3 };
+            let mut expr = Expr::constant(1);
+            for (name, &c) in names.iter().zip(coefficients).take(n) {
+                let term = Expr::symbol(name).checked_mul(&Expr::constant(c)).unwrap();
+                expr = expr.checked_add(&term).unwrap();
+            }
+            let bounds: Vec<_> = names.iter().map(|&name| (name, -4..6)).take(n).collect();
+
+            for target in -40..40 {
+                let mut expected = None;
+                'search: for x in -4..6 {
+                    for y in -4..6 {
+                        for z in -4..6 {
+                            let values = [x, y, z];
+                            let sum: i64 = (0..n).map(|i| coefficients[i] * values[i]).sum();
+                            if sum + 1 == target {
+                                expected = Some(values[..n].to_vec());
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+
+                let got = solve(&expr, target, &bounds);
+                assert_eq!(got.is_some(), expected.is_some(), "{} = {}", expr, target);
+                if let (Some(got), Some(expected)) = (got, expected) {
+                    assert_eq!(got[0], expected[0], "{} = {}", expr, target);
+                    let sum: i64 = (0..n).map(|i| coefficients[i] * got[i]).sum();
+                    assert_eq!(sum + 1, target);
+                    assert!(got.iter().all(|x| (-4..6).contains(x)));
+                }
+            }
+        }
+    }
+}
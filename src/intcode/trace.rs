@@ -0,0 +1,133 @@
+use super::Opcode;
+
+use std::fmt;
+use std::io;
+use std::io::Write;
+
+// One executed instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event<W = i64> {
+    pub pc: usize,
+    pub opcode: Opcode,
+    // Values of the operands the instruction read, in order. The operand an
+    // instruction writes to is reported in write instead.
+    pub operands: Vec<W>,
+    // Address and value written, if any.
+    pub write: Option<(usize, W)>,
+    // Relative base when the instruction started.
+    pub relative_base: i64,
+}
+
+// Formats the event as a single line, for example
+// "12 rb=0 add 3 4 -> [10]=7".
+impl<W: fmt::Display> fmt::Display for Event<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} rb={} {}",
+            self.pc,
+            self.relative_base,
+            self.opcode.mnemonic()
+        )?;
+        for x in self.operands.iter() {
+            write!(f, " {}", x)?;
+        }
+        if let Some((address, value)) = &self.write {
+            write!(f, " -> [{}]={}", address, value)?;
+        }
+        Ok(())
+    }
+}
+
+// Receives every instruction a Computer executes. See Computer::set_tracer.
+pub trait Tracer<W = i64> {
+    fn trace(&mut self, event: &Event<W>);
+
+    // Called once tracing is done. Returns any error the tracer hit.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<W, F: FnMut(&Event<W>)> Tracer<W> for F {
+    fn trace(&mut self, event: &Event<W>) {
+        self(event)
+    }
+}
+
+// Writes one line per event, suitable for diffing two runs.
+pub struct LineTracer<W: Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> LineTracer<W> {
+    pub fn new(out: W) -> LineTracer<W> {
+        LineTracer { out, error: None }
+    }
+}
+
+impl<T: fmt::Display, W: Write> Tracer<T> for LineTracer<W> {
+    fn trace(&mut self, event: &Event<T>) {
+        if self.error.is_none() {
+            if let Err(e) = writeln!(self.out, "{}", event) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Computer, State};
+
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_trace() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let sink = lines.clone();
+
+        let mut cpu = Computer::new(&[109, 20, 203, 0, 1001, 20, 5, 21, 4, 21, 99]);
+        cpu.set_tracer(Box::new(move |e: &Event| {
+            sink.lock().unwrap().push(e.to_string())
+        }));
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        cpu.input(7).unwrap();
+        assert_eq!(cpu.execute(), Ok(State::Output(12)));
+        assert_eq!(cpu.execute(), Ok(State::Halted));
+
+        assert_eq!(
+            *lines.lock().unwrap(),
+            vec![
+                "0 rb=0 arb 20",
+                "2 rb=20 in -> [20]=7",
+                "4 rb=20 add 7 5 -> [21]=12",
+                "8 rb=20 out 12",
+                "10 rb=20 hlt",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_tracer() {
+        let mut tracer = LineTracer::new(Vec::new());
+        tracer.trace(&Event::<i64> {
+            pc: 5,
+            opcode: Opcode::Halt,
+            operands: vec![],
+            write: None,
+            relative_base: -3,
+        });
+        assert!(Tracer::<i64>::finish(&mut tracer).is_ok());
+        assert_eq!(tracer.out, b"5 rb=-3 hlt\n");
+    }
+}
@@ -0,0 +1,147 @@
+use num::{BigInt, ToPrimitive};
+
+use std::fmt;
+use std::str::FromStr;
+
+// A value held in a memory cell. Arithmetic is checked: an add or mul whose
+// result doesn't fit fails with Error::Overflow instead of wrapping.
+//
+// Implemented for i64, the default, i128, and BigInt, which never overflows.
+pub trait Word: Clone + Ord + fmt::Debug + fmt::Display + FromStr + Send + 'static {
+    fn from_i64(x: i64) -> Self;
+
+    // Returns the value as an i64, or None if it doesn't fit.
+    fn to_i64(&self) -> Option<i64>;
+
+    fn checked_add(&self, other: &Self) -> Option<Self>;
+    fn checked_mul(&self, other: &Self) -> Option<Self>;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::from_i64(0)
+    }
+}
+
+impl Word for i64 {
+    fn from_i64(x: i64) -> i64 {
+        x
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        Some(*self)
+    }
+
+    fn checked_add(&self, other: &i64) -> Option<i64> {
+        i64::checked_add(*self, *other)
+    }
+
+    fn checked_mul(&self, other: &i64) -> Option<i64> {
+        i64::checked_mul(*self, *other)
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+}
+
+impl Word for i128 {
+    fn from_i64(x: i64) -> i128 {
+        i128::from(x)
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        ToPrimitive::to_i64(self)
+    }
+
+    fn checked_add(&self, other: &i128) -> Option<i128> {
+        i128::checked_add(*self, *other)
+    }
+
+    fn checked_mul(&self, other: &i128) -> Option<i128> {
+        i128::checked_mul(*self, *other)
+    }
+}
+
+impl Word for BigInt {
+    fn from_i64(x: i64) -> BigInt {
+        BigInt::from(x)
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        ToPrimitive::to_i64(self)
+    }
+
+    fn checked_add(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self + other)
+    }
+
+    fn checked_mul(&self, other: &BigInt) -> Option<BigInt> {
+        Some(self * other)
+    }
+}
+
+// Converts a program, as returned by read_program, to another word type.
+pub fn widen<W: Word>(program: &[i64]) -> Vec<W> {
+    program.iter().map(|&x| W::from_i64(x)).collect()
+}
+
+// Returns x as an i64, clamping values that don't fit. Used to report
+// out-of-range values in errors.
+pub(super) fn saturate<W: Word>(x: &W) -> i64 {
+    match x.to_i64() {
+        Some(x) => x,
+        None if *x < W::from_i64(0) => i64::MIN,
+        None => i64::MAX,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{Computer, Error, State};
+
+    // Reads x, then repeatedly squares it and outputs the result.
+    const SQUARE: [i64; 12] = [3, 11, 2, 11, 11, 11, 4, 11, 1105, 1, 2, 0];
+
+    fn squares<W: Word>(x: i64) -> (Vec<W>, Error) {
+        let mut cpu = Computer::<W>::widened(&SQUARE);
+        assert_eq!(cpu.execute(), Ok(State::InputRequested));
+        cpu.input(W::from_i64(x)).unwrap();
+
+        let mut outputs = Vec::new();
+        loop {
+            match cpu.execute() {
+                Ok(State::Output(x)) => outputs.push(x),
+                Ok(state) => panic!("unexpected state {:?}", state),
+                Err(e) => return (outputs, e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_checked_i64() {
+        let (outputs, err) = squares::<i64>(1 << 20);
+        assert_eq!(outputs, vec![1 << 40]);
+        assert_eq!(err, Error::Overflow { pc: 2 });
+    }
+
+    #[test]
+    fn test_i128() {
+        let (outputs, err) = squares::<i128>(1 << 20);
+        assert_eq!(outputs, vec![1 << 40, 1 << 80]);
+        assert_eq!(err, Error::Overflow { pc: 2 });
+    }
+
+    #[test]
+    fn test_bigint() {
+        let mut cpu = Computer::<BigInt>::widened(&SQUARE);
+        cpu.execute().unwrap();
+        cpu.input(BigInt::from(3)).unwrap();
+        for _ in 0..7 {
+            cpu.execute().unwrap();
+        }
+        // 3^(2^8)
+        let expected = (0..8).fold(BigInt::from(3), |x, _| &x * &x);
+        assert_eq!(cpu.execute(), Ok(State::Output(expected)));
+        assert_eq!(saturate(&(&cpu.ram[11] * BigInt::from(-2))), i64::MIN);
+    }
+}
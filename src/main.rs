@@ -1,4 +1,4 @@
-mod intcode;
+use advent2019::intcode;
 
 mod day01;
 mod day02;